@@ -0,0 +1,147 @@
+// Publication dates, which Google Books (and so the database) has as "1965", "1965-08" or
+// "1965-08-01", and how to show them when choosing between books.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::locale::Locale;
+
+/// A publication date, as precise as it is known.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PublishedDate {
+    pub year: u16,
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+}
+
+impl PublishedDate {
+    /// Parse a date like "1965", "1965-08" or "1965-08-01".
+    pub fn parse(date: &str) -> Option<Self> {
+        let mut parts = date.trim().split('-');
+        let year = parts.next().filter(|year| year.len() == 4)?.parse().ok()?;
+        let month = match parts.next() {
+            Some(month) => Some(
+                month
+                    .parse()
+                    .ok()
+                    .filter(|month| (1..=12).contains(month))?,
+            ),
+            None => None,
+        };
+        let day = match parts.next() {
+            Some(day) => Some(day.parse().ok().filter(|day| (1..=31).contains(day))?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { year, month, day })
+    }
+}
+
+/// How to show publication dates of books and entries.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DateDisplay {
+    /// As stored, e.g. "1965-08-01".
+    Raw,
+    /// Only the year, e.g. "1965".
+    Year,
+    /// Written out for the locale, e.g. "1 August 1965".
+    Full,
+}
+
+impl DateDisplay {
+    /// Format `date` this way, written out for `locale`. Dates that can't be parsed are shown as
+    /// they are.
+    pub fn format(self, date: &str, locale: Locale) -> String {
+        let parsed = match self {
+            DateDisplay::Raw => None,
+            DateDisplay::Year | DateDisplay::Full => PublishedDate::parse(date),
+        };
+        match (self, parsed) {
+            (DateDisplay::Year, Some(parsed)) => parsed.year.to_string(),
+            (DateDisplay::Full, Some(parsed)) => locale.format_date(&parsed),
+            _ => date.to_string(),
+        }
+    }
+}
+
+/// Something that is displayed with a publication date (and maybe other numbers), which can be
+/// shown in different ways and for different locales.
+pub trait DisplayDates {
+    fn fmt_dates(&self, f: &mut Formatter<'_>, dates: DateDisplay, locale: Locale) -> fmt::Result;
+
+    fn with_dates(&self, dates: DateDisplay, locale: Locale) -> WithDates<'_, Self>
+    where
+        Self: Sized,
+    {
+        WithDates(self, dates, locale)
+    }
+}
+
+/// Displays the wrapped value with its dates shown in the given way.
+pub struct WithDates<'a, T>(&'a T, DateDisplay, Locale);
+
+impl<T: DisplayDates> Display for WithDates<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt_dates(f, self.1, self.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_parsed() {
+        assert_eq!(
+            PublishedDate::parse("1965-08-01"),
+            Some(PublishedDate {
+                year: 1965,
+                month: Some(8),
+                day: Some(1)
+            })
+        );
+        assert_eq!(
+            PublishedDate::parse(" 1965-08"),
+            Some(PublishedDate {
+                year: 1965,
+                month: Some(8),
+                day: None
+            })
+        );
+        assert_eq!(
+            PublishedDate::parse("1965"),
+            Some(PublishedDate {
+                year: 1965,
+                month: None,
+                day: None
+            })
+        );
+        assert_eq!(PublishedDate::parse("1965-13"), None);
+        assert_eq!(PublishedDate::parse("65"), None);
+        assert_eq!(PublishedDate::parse("ca. 1965"), None);
+        assert_eq!(PublishedDate::parse("1965-08-01-02"), None);
+    }
+
+    #[test]
+    fn dates_are_formatted() {
+        let locale = Locale::EnGb;
+        assert_eq!(DateDisplay::Raw.format("1965-08-01", locale), "1965-08-01");
+        assert_eq!(DateDisplay::Year.format("1965-08-01", locale), "1965");
+        assert_eq!(
+            DateDisplay::Full.format("1965-08-01", locale),
+            "1 August 1965"
+        );
+        assert_eq!(DateDisplay::Full.format("1965-08", locale), "August 1965");
+        assert_eq!(DateDisplay::Full.format("1965", locale), "1965");
+        assert_eq!(DateDisplay::Year.format("ca. 1965", locale), "ca. 1965");
+        assert_eq!(
+            DateDisplay::Full.format("1965-08-01", Locale::EnUs),
+            "August 1, 1965"
+        );
+        assert_eq!(
+            DateDisplay::Raw.format("1965-08-01", Locale::De),
+            "1965-08-01"
+        );
+    }
+}
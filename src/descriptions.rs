@@ -12,9 +12,287 @@
 //   Some descriptions use a reasonable `<p>A paragraph.</p>` syntax.
 //   Others do something like `A paragraph.<p>`, where a single (open) `p` tag seems to indicate a
 //   paragraph end/break, and there are no closing tags.
-//   Yet others don't use paragraphs and instead just specify line breaks using `<br>`.
+//   Yet others don't use paragraphs and instead just specify line breaks using `<br>` (or the
+//   self-closing `<br/>` and `<br />`). Attributes on any of these tags are ignored.
+// - Raw line breaks. Some descriptions contain literal newlines or the Unicode line and paragraph
+//   separators (U+2028, U+2029) instead of (or in addition to) tags. These are treated like `<br>`.
 
 use miette::Result;
+use std::fmt::Display;
+
+/// A block of a page body, as understood by Notion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(RichText),
+    /// A heading of level 1 to 3.
+    Heading(u8, RichText),
+    BulletedListItem(RichText),
+    NumberedListItem(RichText),
+    Quote(RichText),
+    /// A collapsible block, showing only the summary text until expanded.
+    Toggle(RichText, Vec<Block>),
+    /// A collapsible heading of level 1 to 3, with the blocks nested under it.
+    ToggleHeading(u8, RichText, Vec<Block>),
+}
+
+/// How a description is laid out on the page.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DescriptionStyle {
+    /// Plain paragraphs.
+    Paragraph,
+    /// Paragraphs are turned into quote blocks.
+    Quote,
+    /// The whole description is collapsed into a single toggle block.
+    Toggle,
+    /// The whole description is nested under a collapsible "Description" heading.
+    Heading,
+}
+
+impl DescriptionStyle {
+    pub fn apply(self, blocks: Vec<Block>) -> Vec<Block> {
+        match self {
+            DescriptionStyle::Paragraph => blocks,
+            DescriptionStyle::Quote => blocks
+                .into_iter()
+                .map(|block| match block {
+                    Block::Paragraph(text) => Block::Quote(text),
+                    other => other,
+                })
+                .collect(),
+            DescriptionStyle::Toggle => vec![Block::Toggle(description_title(), blocks)],
+            DescriptionStyle::Heading => {
+                vec![Block::ToggleHeading(2, description_title(), blocks)]
+            }
+        }
+    }
+}
+
+impl Block {
+    /// Apply `f` to all text in the block, including nested blocks.
+    fn map_text(self, f: &impl Fn(&str) -> String) -> Self {
+        match self {
+            Block::Paragraph(text) => Block::Paragraph(text.map_text(f)),
+            Block::Heading(level, text) => Block::Heading(level, text.map_text(f)),
+            Block::BulletedListItem(text) => Block::BulletedListItem(text.map_text(f)),
+            Block::NumberedListItem(text) => Block::NumberedListItem(text.map_text(f)),
+            Block::Quote(text) => Block::Quote(text.map_text(f)),
+            Block::Toggle(text, children) => Block::Toggle(
+                text.map_text(f),
+                children
+                    .into_iter()
+                    .map(|block| block.map_text(f))
+                    .collect(),
+            ),
+            Block::ToggleHeading(level, text, children) => Block::ToggleHeading(
+                level,
+                text.map_text(f),
+                children
+                    .into_iter()
+                    .map(|block| block.map_text(f))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Replace typographic punctuation in all blocks with plain ASCII, see `plain_punctuation`.
+pub fn normalize_punctuation(blocks: Vec<Block>) -> Vec<Block> {
+    blocks
+        .into_iter()
+        .map(|block| block.map_text(&plain_punctuation))
+        .collect()
+}
+
+/// Replace the well-known typographic punctuation with plain ASCII: Curly quotes become straight
+/// quotes, en dashes become hyphens, em dashes two hyphens, and ellipses three dots. Everything
+/// else is left alone.
+pub fn plain_punctuation(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' => plain.push('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201E}' => plain.push('"'),
+            '\u{2013}' => plain.push('-'),
+            '\u{2014}' => plain.push_str("--"),
+            '\u{2026}' => plain.push_str("..."),
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+/// Words that are abbreviated with a period that doesn't end the sentence, in lowercase.
+const ABBREVIATIONS: [&str; 16] = [
+    "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "e.g", "i.e", "cf", "vol", "ed",
+    "inc", "ltd",
+];
+
+/// Split a description that is a single paragraph into paragraphs of `sentences_per_paragraph`
+/// sentences each, since a long wall of text is hard to read. Descriptions that already have
+/// several paragraphs (or line breaks) are left alone.
+pub fn split_sentences(blocks: Vec<Block>, sentences_per_paragraph: usize) -> Vec<Block> {
+    let text = match &blocks[..] {
+        [Block::Paragraph(text)] if !text.fragments.iter().any(|frag| frag.text.contains('\n')) => {
+            text
+        }
+        _ => return blocks,
+    };
+    let plain = text
+        .fragments
+        .iter()
+        .map(|frag| frag.text.as_str())
+        .collect::<String>();
+    let cuts = sentence_starts(&plain)
+        .into_iter()
+        .skip(sentences_per_paragraph.max(1) - 1)
+        .step_by(sentences_per_paragraph.max(1))
+        .collect::<Vec<_>>();
+    split_at(text, &cuts)
+        .into_iter()
+        .map(Block::Paragraph)
+        .collect()
+}
+
+/// The byte offsets in `text` at which a sentence other than the first starts. A sentence ends
+/// with a period, question or exclamation mark (and maybe closing quotes or brackets), followed by
+/// whitespace and an uppercase letter, digit or opening quote. Periods after abbreviations and
+/// initials (like in "J. R. R. Tolkien") don't end sentences.
+fn sentence_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let (end, c) = chars[i];
+        i += 1;
+        if !matches!(c, '.' | '!' | '?' | '\u{2026}') {
+            continue;
+        }
+        while i < chars.len() && "\"')]\u{2019}\u{201D}".contains(chars[i].1) {
+            i += 1;
+        }
+        let mut next = i;
+        while next < chars.len() && chars[next].1.is_whitespace() {
+            next += 1;
+        }
+        if next == i || next == chars.len() {
+            continue;
+        }
+        let (start, first) = chars[next];
+        let starts_sentence = first.is_uppercase()
+            || first.is_ascii_digit()
+            || "\"'(\u{2018}\u{201C}".contains(first);
+        if starts_sentence && !(c == '.' && is_abbreviation(&text[..end])) {
+            starts.push(start);
+        }
+        i = next;
+    }
+    starts
+}
+
+/// Whether the word at the end of `text` is an abbreviation or initial, when followed by a period.
+fn is_abbreviation(text: &str) -> bool {
+    let word = text
+        .rsplit(|c: char| c.is_whitespace() || "\"'(\u{2018}\u{201C}".contains(c))
+        .next()
+        .unwrap_or_default();
+    let is_initial = word
+        .split('.')
+        .all(|part| part.chars().count() == 1 && part.chars().all(char::is_uppercase));
+    is_initial || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Split `text` at the byte offsets `cuts` (into the text of all fragments together), trimming
+/// whitespace around the cuts.
+fn split_at(text: &RichText, cuts: &[usize]) -> Vec<RichText> {
+    let mut parts = vec![RichText { fragments: vec![] }];
+    let mut offset = 0;
+    let mut cuts = cuts.iter().peekable();
+    for frag in &text.fragments {
+        let mut rest = frag.text.as_str();
+        let mut start = offset;
+        while let Some(&&cut) = cuts.peek().filter(|&&&cut| cut < offset + frag.text.len()) {
+            let (before, after) = rest.split_at(cut - start);
+            parts.last_mut().unwrap().push(before, frag.style);
+            parts.push(RichText { fragments: vec![] });
+            rest = after;
+            start = cut;
+            cuts.next();
+        }
+        parts.last_mut().unwrap().push(rest, frag.style);
+        offset += frag.text.len();
+    }
+    for part in &mut parts {
+        part.fragments.retain(|frag| !frag.text.is_empty());
+        while let Some(last) = part.fragments.last_mut() {
+            last.text.truncate(last.text.trim_end().len());
+            if !last.text.is_empty() {
+                break;
+            }
+            part.fragments.pop();
+        }
+    }
+    parts.retain(|part| !part.fragments.is_empty());
+    parts
+}
+
+/// Replace double quotes in all blocks with those used in `language` (an ISO 639-1 code like "de"
+/// or "de-AT"), see `localized_quotes`. Languages other than German and French are left alone.
+pub fn localize_quotes(blocks: Vec<Block>, language: &str) -> Vec<Block> {
+    let marks = match quotation_marks(language) {
+        Some(marks) => marks,
+        None => return blocks,
+    };
+    blocks
+        .into_iter()
+        .map(|block| block.map_text(&|text| localized_quotes(text, marks)))
+        .collect()
+}
+
+/// The opening and closing double quotation marks used in `language`, if it's one we know.
+fn quotation_marks(language: &str) -> Option<(char, char)> {
+    let language = language.split('-').next()?.to_ascii_lowercase();
+    match language.as_str() {
+        "de" => Some(('\u{201E}', '\u{201C}')),
+        "fr" => Some(('\u{00AB}', '\u{00BB}')),
+        _ => None,
+    }
+}
+
+/// Replace straight and English curly double quotes with `open` and `close`. Whether a quote opens
+/// or closes is guessed from the characters around it. Nothing but the quotes themselves is
+/// changed; in particular, single quotes are left alone since they double as apostrophes.
+fn localized_quotes(text: &str, (open, close): (char, char)) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            if !matches!(c, '"' | '\u{201C}' | '\u{201D}') {
+                return c;
+            }
+            let previous = i.checked_sub(1).map(|i| chars[i]);
+            let opens = match (previous, chars.get(i + 1)) {
+                (Some(previous), _) => {
+                    previous.is_whitespace() || "([{\u{2013}\u{2014}".contains(previous)
+                }
+                // At the start of a fragment, look at what's being quoted instead.
+                (None, Some(&next)) => !next.is_whitespace() && !".,;:!?)]}".contains(next),
+                (None, None) => false,
+            };
+            if opens {
+                open
+            } else {
+                close
+            }
+        })
+        .collect()
+}
+
+fn description_title() -> RichText {
+    RichText {
+        fragments: vec![TextFragment::new("Description", TextStyle::unstyled())],
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RichText {
@@ -27,8 +305,46 @@ pub struct TextFragment {
     pub style: TextStyle,
 }
 
+/// Renders the text with markdown-style markup (`**bold**`, `*italic*`), for inspecting parse
+/// results.
+impl Display for RichText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for frag in &self.fragments {
+            let markup = match (frag.style.bold, frag.style.italic) {
+                (false, false) => "",
+                (false, true) => "*",
+                (true, false) => "**",
+                (true, true) => "***",
+            };
+            write!(f, "{markup}{}{markup}", frag.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl RichText {
+    /// Add `text` with `style` to the end, merging it into the last fragment if that has the same
+    /// style.
+    fn push(&mut self, text: &str, style: TextStyle) {
+        match self.fragments.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(text),
+            _ => self.fragments.push(TextFragment::new(text, style)),
+        }
+    }
+
+    fn map_text(self, f: &impl Fn(&str) -> String) -> Self {
+        RichText {
+            fragments: self
+                .fragments
+                .into_iter()
+                .map(|frag| TextFragment::new(f(&frag.text), frag.style))
+                .collect(),
+        }
+    }
+}
+
 impl TextFragment {
-    fn new(text: impl ToString, style: TextStyle) -> Self {
+    pub fn new(text: impl ToString, style: TextStyle) -> Self {
         Self {
             text: text.to_string(),
             style,
@@ -44,28 +360,28 @@ pub struct TextStyle {
 
 #[allow(unused)] // These are currently only used in cfg(test) but seem nice enough to keep generally.
 impl TextStyle {
-    fn unstyled() -> Self {
+    pub fn unstyled() -> Self {
         Self {
             bold: false,
             italic: false,
         }
     }
 
-    fn bold() -> Self {
+    pub fn bold() -> Self {
         Self {
             bold: true,
             italic: false,
         }
     }
 
-    fn italic() -> Self {
+    pub fn italic() -> Self {
         Self {
             bold: false,
             italic: true,
         }
     }
 
-    fn bold_italic() -> Self {
+    pub fn bold_italic() -> Self {
         Self {
             bold: true,
             italic: true,
@@ -74,18 +390,31 @@ impl TextStyle {
 }
 
 pub fn parse_text(text: &str) -> Result<RichText> {
+    // Turn raw line breaks into tags up front, so they get the same whitespace trimming and
+    // collapsing as any other break.
+    let text = text
+        .replace("\r\n", "<br>")
+        .replace(['\n', '\r', '\u{2028}', '\u{2029}'], "<br>");
+    let text = text.as_str();
+
     // We're gonna assume that the text *either* uses reasonable `<p>text</p>` syntax *or* the
     // weird `text<p>` syntax. To keep things simple (and not worrying too much about performance),
     // we first figure out which one of these it is in one pass, and then do the actual parsing
     // in another pass afterwards.
 
-    // If there is a `</p>`, we assume proper paragraphs. If there isn't, either there are no
-    // (`<p>`-based) paragraphs at all, or they are the broken variety.
-    let reasonable_paragraphs = text.contains("</p>");
+    // If there is a closing `p` tag, we assume proper paragraphs. If there isn't, either there are
+    // no (`<p>`-based) paragraphs at all, or they are the broken variety. Tags are recognized the
+    // same way as during parsing, so `<p align="justify">text</p >` counts as well.
+    let reasonable_paragraphs = has_closing_paragraph(text);
 
     let mut fragments = Vec::new();
+    // Whether any of the fragments pushed so far contain non-whitespace text.
+    let mut has_text = false;
 
     let mut style_stack = Vec::new();
+    // Number of style tags opened after the style stack reached `MAX_STYLE_DEPTH`. These don't
+    // change the style any more, but still have to be closed again.
+    let mut overflowed_styles = 0;
     let mut current_style = TextStyle {
         bold: false,
         italic: false,
@@ -110,19 +439,27 @@ pub fn parse_text(text: &str) -> Result<RichText> {
                 // No matter whether we close a tag or start a new one, we will have a different
                 // style for subsequent text. Push a fragment with the text collected so far with
                 // the current style and start a new fragment with the new style.
+                has_text |= !current_fragment.trim().is_empty();
                 fragments.push(TextFragment::new(current_fragment, current_style));
                 current_fragment = String::new();
 
                 if tag.open {
-                    style_stack.push(current_style);
-                    match tag.ty {
-                        TagType::Bold => current_style.bold = true,
-                        TagType::Italic => current_style.italic = true,
-                        TagType::Paragraph | TagType::Linebreak => unreachable!(),
+                    if style_stack.len() < MAX_STYLE_DEPTH {
+                        style_stack.push(current_style);
+                        match tag.ty {
+                            TagType::Bold => current_style.bold = true,
+                            TagType::Italic => current_style.italic = true,
+                            TagType::Paragraph | TagType::Linebreak => unreachable!(),
+                        }
+                    } else {
+                        overflowed_styles += 1;
                     }
-                } else {
-                    current_style = style_stack.pop().unwrap();
+                } else if overflowed_styles > 0 {
+                    overflowed_styles -= 1;
+                } else if let Some(style) = style_stack.pop() {
+                    current_style = style;
                 }
+                // Otherwise, this closes a tag that was never opened, and we just ignore it.
             } else {
                 let push_newline = match tag.ty {
                     TagType::Linebreak => true,
@@ -142,8 +479,15 @@ pub fn parse_text(text: &str) -> Result<RichText> {
                 if push_newline {
                     // The markup might have whitespace around the tag resulting in a newline, but
                     // we want to avoid trailing or leading whitespace.
+                    // Trimming also removes a newline we might have just pushed for a previous
+                    // tag, so consecutive breaks collapse into a single one.
                     current_fragment.truncate(current_fragment.trim_end().len());
-                    current_fragment.push('\n');
+
+                    // A break before any actual text would just be an empty line at the start.
+                    let at_start = current_fragment.is_empty() && !has_text;
+                    if !at_start {
+                        current_fragment.push('\n');
+                    }
                     skip_until_nonwhitespace = true;
                 }
             }
@@ -154,7 +498,7 @@ pub fn parse_text(text: &str) -> Result<RichText> {
             if skip_until_nonwhitespace {
                 cursor += text[cursor..]
                     .find(|c: char| !c.is_whitespace())
-                    .unwrap_or(text.len());
+                    .unwrap_or(text.len() - cursor);
             }
 
             search_start = cursor;
@@ -170,20 +514,24 @@ pub fn parse_text(text: &str) -> Result<RichText> {
         }
     }
 
-    if search_start < text.len() {
-        // We did not find a further '<', so just take all the remaining text and push one last
-        // fragment.
-        current_fragment.push_str(&text[search_start..]);
+    if cursor < text.len() {
+        // We did not find a further tag, so just take all the remaining text (including any '<'
+        // that turned out not to start a tag) and push one last fragment.
+        current_fragment.push_str(&text[cursor..]);
     }
     fragments.push(TextFragment::new(current_fragment, current_style));
 
     // To be nice, filter out fragments that are entirely empty.
     fragments.retain(|frag| !frag.text.is_empty());
 
-    // Trim whitespace off the very end of the text.
-    fragments
-        .last_mut()
-        .map(|last| last.text.truncate(last.text.trim_end().len()));
+    // Trim whitespace off the very end of the text, dropping fragments that end up empty.
+    while let Some(last) = fragments.last_mut() {
+        last.text.truncate(last.text.trim_end().len());
+        if !last.text.is_empty() {
+            break;
+        }
+        fragments.pop();
+    }
 
     Ok(RichText { fragments })
 }
@@ -198,10 +546,7 @@ enum TagType {
 
 impl TagType {
     fn is_style(self) -> bool {
-        match self {
-            TagType::Bold | TagType::Italic => true,
-            _ => false,
-        }
+        matches!(self, TagType::Bold | TagType::Italic)
     }
 }
 
@@ -211,19 +556,143 @@ struct Tag {
     open: bool,
 }
 
+/// Convert a description to plain text, for users who'd rather not have it parsed: All tags are
+/// removed (with paragraphs and line breaks turned into newlines), and HTML entities are decoded.
+pub fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+
+    let mut rest = text;
+    while let Some(pos) = rest.find('<') {
+        stripped.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let tag_len = rest
+            .bytes()
+            .take(MAX_TAG_LEN)
+            .position(|b| b == b'>')
+            .map(|end| end + 1);
+        let name = rest[1..]
+            .trim_start_matches('/')
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        match tag_len {
+            Some(len) if name.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                if name == "br" || name == "p" {
+                    stripped.push('\n');
+                }
+                rest = &rest[len..];
+            }
+            _ => {
+                // Not a tag after all.
+                stripped.push('<');
+                rest = &rest[1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    decode_entities(&stripped)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decode the named HTML entities that show up in practice, and all numeric ones. Anything else is
+/// left as is.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        decoded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let entity = rest
+            .char_indices()
+            .take(12)
+            .find(|(_, c)| *c == ';')
+            .map(|(end, _)| &rest[1..end]);
+        let replacement = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+
+        match (entity, replacement) {
+            (Some(entity), Some(replacement)) => {
+                decoded.push(replacement);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+/// Style tags nested deeper than this are ignored, to bound memory use on pathological input.
+const MAX_STYLE_DEPTH: usize = 64;
+
+/// Tags we support are short, so there is no need to search for the end of a tag any further than
+/// this. Otherwise, text with many `<` but no `>` would take quadratic time to parse.
+const MAX_TAG_LEN: usize = 64;
+
+fn has_closing_paragraph(text: &str) -> bool {
+    text.match_indices('<').any(|(pos, _)| {
+        matches!(
+            try_parse_tag(&text[pos..]),
+            Some((
+                Tag {
+                    open: false,
+                    ty: TagType::Paragraph
+                },
+                _
+            ))
+        )
+    })
+}
+
 fn try_parse_tag(text: &str) -> Option<(Tag, usize)> {
     let bytes = text.as_bytes();
 
-    let (open, tag_open_length) = if bytes[1] == b'/' {
+    let (open, tag_open_length) = if bytes.get(1) == Some(&b'/') {
         (false, 2)
     } else {
         (true, 1)
     };
 
-    let close_braces_pos = text.find('>')?;
+    let close_braces_pos = bytes.iter().take(MAX_TAG_LEN).position(|b| *b == b'>')?;
+    if close_braces_pos < tag_open_length {
+        return None;
+    }
+    // Ignore any attributes and the slash of self-closing tags like `<br />`, we only care about
+    // the tag name.
     let tag_text = &bytes[tag_open_length..close_braces_pos];
+    let tag_text = tag_text.strip_suffix(b"/").unwrap_or(tag_text);
+    let tag_name = tag_text
+        .split(|b| b.is_ascii_whitespace())
+        .next()
+        .unwrap_or(tag_text);
 
-    let tag_type = match tag_text {
+    let tag_type = match tag_name {
         b"p" => TagType::Paragraph,
         b"br" => TagType::Linebreak,
         b"b" => TagType::Bold,
@@ -238,6 +707,198 @@ fn try_parse_tag(text: &str) -> Option<(Tag, usize)> {
 mod tests {
     use super::*;
 
+    fn paragraph(text: &str) -> Block {
+        Block::Paragraph(RichText {
+            fragments: vec![TextFragment::new(text, TextStyle::unstyled())],
+        })
+    }
+
+    #[test]
+    fn curly_quotes_are_straightened() {
+        assert_eq!(
+            plain_punctuation("\u{201C}It\u{2019}s \u{2018}fine\u{2019},\u{201D} she said."),
+            "\"It's 'fine',\" she said."
+        );
+        assert_eq!(plain_punctuation("\u{201E}Gut\u{201C}"), "\"Gut\"");
+    }
+
+    #[test]
+    fn dashes_and_ellipses_are_spelled_out() {
+        assert_eq!(plain_punctuation("pp. 10\u{2013}20"), "pp. 10-20");
+        assert_eq!(
+            plain_punctuation("Arrakis\u{2014}Dune\u{2014}Desert Planet"),
+            "Arrakis--Dune--Desert Planet"
+        );
+        assert_eq!(plain_punctuation("And then\u{2026}"), "And then...");
+        assert_eq!(
+            plain_punctuation("Caf\u{e9} \u{ab}non\u{bb} \u{2212}1"),
+            "Caf\u{e9} \u{ab}non\u{bb} \u{2212}1"
+        );
+    }
+
+    #[test]
+    fn punctuation_is_normalized_in_nested_blocks() {
+        let blocks = vec![Block::Toggle(
+            RichText {
+                fragments: vec![TextFragment::new("\u{201C}Dune\u{201D}", TextStyle::bold())],
+            },
+            vec![paragraph("A desert\u{2026}")],
+        )];
+        assert_eq!(
+            normalize_punctuation(blocks),
+            vec![Block::Toggle(
+                RichText {
+                    fragments: vec![TextFragment::new("\"Dune\"", TextStyle::bold())],
+                },
+                vec![paragraph("A desert...")],
+            )]
+        );
+    }
+
+    #[test]
+    fn quotes_are_localized() {
+        let german = ('\u{201E}', '\u{201C}');
+        assert_eq!(
+            localized_quotes("Er sagte \"Hallo\" und ging.", german),
+            "Er sagte \u{201E}Hallo\u{201C} und ging."
+        );
+        assert_eq!(
+            localized_quotes("\u{201C}Dune\u{201D} (\"Der W\u{00FC}stenplanet\")", german),
+            "\u{201E}Dune\u{201C} (\u{201E}Der W\u{00FC}stenplanet\u{201C})"
+        );
+        // Already German quotes and apostrophes stay as they are.
+        assert_eq!(
+            localized_quotes("\u{201E}Dune\u{201C}, Herbert's Roman", german),
+            "\u{201E}Dune\u{201C}, Herbert's Roman"
+        );
+
+        let french = ('\u{00AB}', '\u{00BB}');
+        assert_eq!(
+            localized_quotes("Il a dit \"Bonjour\".", french),
+            "Il a dit \u{00AB}Bonjour\u{00BB}."
+        );
+    }
+
+    #[test]
+    fn quotes_are_localized_by_language() {
+        let blocks = vec![Block::Paragraph(RichText {
+            fragments: vec![
+                TextFragment::new("Der Roman ", TextStyle::unstyled()),
+                TextFragment::new("\"Dune", TextStyle::italic()),
+                TextFragment::new("\" ist ein Klassiker.", TextStyle::unstyled()),
+            ],
+        })];
+        assert_eq!(
+            localize_quotes(blocks.clone(), "de-AT"),
+            vec![Block::Paragraph(RichText {
+                fragments: vec![
+                    TextFragment::new("Der Roman ", TextStyle::unstyled()),
+                    TextFragment::new("\u{201E}Dune", TextStyle::italic()),
+                    TextFragment::new("\u{201C} ist ein Klassiker.", TextStyle::unstyled()),
+                ],
+            })]
+        );
+        assert_eq!(localize_quotes(blocks.clone(), "en"), blocks);
+    }
+
+    #[test]
+    fn tags_are_stripped() {
+        assert_eq!(
+            strip_tags("<p>A <b>bold</b> start.</p><p>Next<br/>line, 1 < 2.</p>"),
+            "A bold start.\nNext\nline, 1 < 2."
+        );
+        assert_eq!(
+            strip_tags("Broken<p>paragraphs<p>\n\n<i>here</i>"),
+            "Broken\nparagraphs\nhere"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(
+            strip_tags("Tom &amp; Jerry &lt;3 &quot;quoted&quot; &#8212; &#x263A; &unknown; AT&T"),
+            "Tom & Jerry <3 \"quoted\" \u{2014} \u{263A} &unknown; AT&T"
+        );
+    }
+
+    #[test]
+    fn paragraphs_with_attributes() {
+        let expected = parse_text("<p>text</p><p>more</p>").unwrap();
+        assert_eq!(
+            parse_text("<p align=\"justify\">text</p><p align=\"justify\">more</p>").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_text("<p align=\"justify\">text</p ><p>more</p >").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_text("<p align=\"justify\">text</p>").unwrap(),
+            parse_text("<p>text</p>").unwrap()
+        );
+    }
+
+    #[test]
+    fn self_closing_and_attribute_linebreaks() {
+        let expected = parse_text("a<br>b").unwrap();
+        assert_eq!(parse_text("a<br/>b").unwrap(), expected);
+        assert_eq!(parse_text("a<br />b").unwrap(), expected);
+        assert_eq!(parse_text("a<br class=\"x\">b").unwrap(), expected);
+        assert_eq!(
+            parse_text("<p class=\"intro\">a</p><b style=\"x\">b</b>").unwrap(),
+            RichText {
+                fragments: vec![
+                    TextFragment::new("a\n", TextStyle::unstyled()),
+                    TextFragment::new("b", TextStyle::bold()),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn render_rich_text() {
+        let text =
+            parse_text("Some <b>bold</b>, <i>italic</i> and <b><i>both</i></b>.<br>Next").unwrap();
+        assert_eq!(
+            text.to_string(),
+            "Some **bold**, *italic* and ***both***.\nNext"
+        );
+    }
+
+    #[test]
+    fn description_styles() {
+        let blocks = vec![
+            paragraph("First."),
+            Block::BulletedListItem(RichText {
+                fragments: vec![TextFragment::new("Item", TextStyle::unstyled())],
+            }),
+        ];
+
+        assert_eq!(DescriptionStyle::Paragraph.apply(blocks.clone()), blocks);
+        assert_eq!(
+            DescriptionStyle::Quote.apply(blocks.clone()),
+            vec![
+                Block::Quote(RichText {
+                    fragments: vec![TextFragment::new("First.", TextStyle::unstyled())],
+                }),
+                blocks[1].clone(),
+            ]
+        );
+        assert_eq!(
+            DescriptionStyle::Toggle.apply(blocks.clone()),
+            vec![Block::Toggle(
+                RichText {
+                    fragments: vec![TextFragment::new("Description", TextStyle::unstyled())],
+                },
+                blocks.clone()
+            )]
+        );
+        assert_eq!(
+            DescriptionStyle::Heading.apply(blocks.clone()),
+            vec![Block::ToggleHeading(2, description_title(), blocks)]
+        );
+    }
+
     #[test]
     fn simple_bold() {
         assert_eq!(
@@ -291,6 +952,207 @@ mod tests {
         );
     }
 
+    #[test]
+    fn leading_consecutive_paragraphs() {
+        assert_eq!(
+            parse_text("<p><p>text").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new("text", TextStyle::unstyled())]
+            }
+        );
+    }
+
+    #[test]
+    fn trailing_consecutive_paragraphs() {
+        assert_eq!(
+            parse_text("text<p><p>").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new("text", TextStyle::unstyled())]
+            }
+        );
+    }
+
+    #[test]
+    fn consecutive_breaks_collapse() {
+        assert_eq!(
+            parse_text("one<p><p>two<br> <br>three").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new("one\ntwo\nthree", TextStyle::unstyled())]
+            }
+        );
+    }
+
+    #[test]
+    fn trailing_break_inside_style() {
+        assert_eq!(
+            parse_text("<b>text</b><br><i> </i>").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new("text", TextStyle::bold())]
+            }
+        );
+    }
+
+    #[test]
+    fn raw_newlines() {
+        assert_eq!(
+            parse_text("First line.\nSecond line.\r\n\r\nThird line.\n").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new(
+                    "First line.\nSecond line.\nThird line.",
+                    TextStyle::unstyled()
+                )]
+            }
+        );
+    }
+
+    #[test]
+    fn unicode_separators() {
+        assert_eq!(
+            parse_text("\u{2029}First paragraph.\u{2029}Second\u{2028}paragraph.").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new(
+                    "First paragraph.\nSecond\nparagraph.",
+                    TextStyle::unstyled()
+                )]
+            }
+        );
+    }
+
+    #[test]
+    fn raw_newlines_between_paragraph_tags() {
+        assert_eq!(
+            parse_text("<p>One.</p>\n<p>Two.</p>").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new("One.\nTwo.", TextStyle::unstyled())]
+            }
+        );
+    }
+
+    #[test]
+    fn text_around_non_tags_is_kept() {
+        assert_eq!(
+            parse_text("a < b, <c> and a trailing <").unwrap(),
+            RichText {
+                fragments: vec![TextFragment::new(
+                    "a < b, <c> and a trailing <",
+                    TextStyle::unstyled()
+                )]
+            }
+        );
+    }
+
+    #[test]
+    fn unmatched_closing_tag_is_ignored() {
+        assert_eq!(
+            parse_text("</b>plain <i>italic</i></i> plain").unwrap(),
+            RichText {
+                fragments: vec![
+                    TextFragment::new("plain ", TextStyle::unstyled()),
+                    TextFragment::new("italic", TextStyle::italic()),
+                    TextFragment::new(" plain", TextStyle::unstyled()),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn huge_pathological_input() {
+        let start = std::time::Instant::now();
+
+        let many_false_tags = "a <".repeat(200_000);
+        let parsed = parse_text(&many_false_tags).unwrap();
+        assert_eq!(parsed.fragments.len(), 1);
+        assert_eq!(parsed.fragments[0].text.len(), many_false_tags.len());
+
+        let deeply_nested = format!("{}text{}", "<b>".repeat(100_000), "</b>".repeat(100_000));
+        let parsed = parse_text(&deeply_nested).unwrap();
+        assert_eq!(
+            parsed.fragments,
+            vec![TextFragment::new("text", TextStyle::bold())]
+        );
+
+        let many_breaks = "<b>x</b><br>".repeat(50_000);
+        parse_text(&many_breaks).unwrap();
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    fn sentences(text: &str, sentences_per_paragraph: usize) -> Vec<Block> {
+        split_sentences(vec![paragraph(text)], sentences_per_paragraph)
+    }
+
+    #[test]
+    fn descriptions_are_split_into_sentences() {
+        assert_eq!(
+            sentences("One. Two! Three? \"Four.\" Five.", 2),
+            vec![
+                paragraph("One. Two!"),
+                paragraph("Three? \"Four.\""),
+                paragraph("Five.")
+            ]
+        );
+        assert_eq!(
+            sentences("One. Two.", 1),
+            vec![paragraph("One."), paragraph("Two.")]
+        );
+        assert_eq!(sentences("One. Two.", 2), vec![paragraph("One. Two.")]);
+        assert_eq!(sentences("One. Two.", 0), sentences("One. Two.", 1));
+    }
+
+    #[test]
+    fn sentences_are_not_split_at_abbreviations() {
+        let text =
+            "Dr. Jones meets J. R. R. Tolkien, e.g. in Oxford. The U.S. Army is not invited. \
+            It ends... Or does it? 1984 was published in 1949. lowercase. Okay";
+        assert_eq!(
+            sentences(text, 1),
+            vec![
+                paragraph("Dr. Jones meets J. R. R. Tolkien, e.g. in Oxford."),
+                paragraph("The U.S. Army is not invited."),
+                paragraph("It ends..."),
+                paragraph("Or does it?"),
+                paragraph("1984 was published in 1949. lowercase."),
+                paragraph("Okay"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sentences_are_split_across_styles() {
+        let text = RichText {
+            fragments: vec![
+                TextFragment::new("First. ", TextStyle::unstyled()),
+                TextFragment::new("Bold. Also bold.", TextStyle::bold()),
+                TextFragment::new(" Last.", TextStyle::unstyled()),
+            ],
+        };
+        assert_eq!(
+            split_sentences(vec![Block::Paragraph(text)], 2),
+            vec![
+                Block::Paragraph(RichText {
+                    fragments: vec![
+                        TextFragment::new("First. ", TextStyle::unstyled()),
+                        TextFragment::new("Bold.", TextStyle::bold()),
+                    ]
+                }),
+                Block::Paragraph(RichText {
+                    fragments: vec![
+                        TextFragment::new("Also bold.", TextStyle::bold()),
+                        TextFragment::new(" Last.", TextStyle::unstyled()),
+                    ]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn paragraphed_descriptions_are_not_split() {
+        let blocks = vec![paragraph("One. Two."), paragraph("Three.")];
+        assert_eq!(split_sentences(blocks.clone(), 1), blocks);
+        let blocks = vec![paragraph("One. Two.\nThree.")];
+        assert_eq!(split_sentences(blocks.clone(), 1), blocks);
+    }
+
     #[test]
     fn mixed_styles_and_paragraphs() {
         assert_eq!(
@@ -1,36 +1,192 @@
-use futures::future;
+use futures::{stream, Future, StreamExt};
 use miette::{miette, Context, IntoDiagnostic, Result};
 use reqwest::{Client, Method, RequestBuilder};
 use serde_derive::Deserialize;
 use serde_json::Value;
-use std::fmt::Display;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+};
 use url::{form_urlencoded::Serializer, Url, UrlQuery};
 
+use crate::{
+    dates::{DateDisplay, DisplayDates, PublishedDate},
+    descriptions::strip_tags,
+    locale::Locale,
+};
+
 pub struct GBooks {
     api_key: String,
+    identifier_preference: Vec<String>,
     client: Client,
+    user_agent: Option<String>,
+    concurrency: usize,
+    verbose: bool,
 }
 
+/// How many volume details to fetch at once by default when searching.
+pub const DEFAULT_SEARCH_CONCURRENCY: usize = 5;
+
+/// How Google Books is named as the source of an entry's metadata.
+pub const SOURCE: &str = "Google Books";
+
 #[derive(Debug, Clone)]
 pub struct GBook {
     pub title: String,
+    pub subtitle: Option<String>,
     pub authors: Vec<String>,
     pub publisher: Option<String>,
     pub published_date: Option<String>,
     pub isbn: Option<String>,
+    /// All industry identifiers of the volume as type and identifier, e.g. `("ISBN_10",
+    /// "0441013597")`, those with a preferred type first.
+    pub identifiers: Vec<(String, String)>,
+    pub page_count: Option<u32>,
+    pub edition: Option<String>,
+    /// The year the book was originally published, for reprints, if the description says so.
+    pub original_year: Option<u16>,
+    pub format: Option<Format>,
+    /// The physical dimensions, as a human-readable summary.
+    pub dimensions: Option<String>,
+    /// The position of the volume within its series, if it's part of one.
+    pub series_position: Option<f64>,
+    /// Whether the volume can be downloaded as an EPUB or PDF, if Google Books says.
+    pub epub_available: Option<bool>,
+    pub pdf_available: Option<bool>,
+    /// The Google Books ID of the volume, and the API URL to fetch exactly this volume again.
+    pub volume_id: Option<String>,
+    pub self_link: Option<String>,
+    /// The language of the volume as an ISO 639-1 code, like "en".
+    pub language: Option<String>,
+    /// The subject categories, like "Fiction / Fantasy / General".
+    pub categories: Vec<String>,
+    /// How many people rated the volume on Google Books, and their average rating from 1 to 5.
+    pub ratings_count: Option<u32>,
+    pub average_rating: Option<f64>,
     pub description: Option<String>,
     pub image_link: Option<String>,
 }
 
+/// The physical (or not) format of a book.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Hardcover,
+    Paperback,
+    Ebook,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Hardcover => "Hardcover",
+            Format::Paperback => "Paperback",
+            Format::Ebook => "Ebook",
+        })
+    }
+}
+
+/// Restrict search results by viewability or ebook availability.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SearchFilter {
+    /// Books with at least part of the text previewable.
+    Partial,
+    /// Books with all of the text viewable.
+    Full,
+    /// Free Google eBooks.
+    FreeEbooks,
+    /// Google eBooks with a price.
+    PaidEbooks,
+    /// All Google eBooks.
+    Ebooks,
+}
+
+impl SearchFilter {
+    fn as_param(self) -> &'static str {
+        match self {
+            SearchFilter::Partial => "partial",
+            SearchFilter::Full => "full",
+            SearchFilter::FreeEbooks => "free-ebooks",
+            SearchFilter::PaidEbooks => "paid-ebooks",
+            SearchFilter::Ebooks => "ebooks",
+        }
+    }
+}
+
+pub struct SearchResults {
+    pub books: Vec<GBook>,
+    /// The total number of books matching the query, of which only the first page is returned.
+    pub total: usize,
+    /// Errors for results whose details could not be retrieved.
+    pub failed: Vec<miette::Error>,
+}
+
+impl GBook {
+    /// The ISBN-13 and ISBN-10 among the identifiers, those of the preferred type first.
+    pub fn isbns(&self) -> Vec<String> {
+        self.identifiers
+            .iter()
+            .filter(|(ty, _)| ty == "ISBN_13" || ty == "ISBN_10")
+            .map(|(_, identifier)| identifier.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl GBook {
+    /// A new book with only a title, to fill in the rest with struct update syntax.
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            subtitle: None,
+            authors: vec![],
+            publisher: None,
+            published_date: None,
+            isbn: None,
+            identifiers: vec![],
+            page_count: None,
+            edition: None,
+            original_year: None,
+            format: None,
+            dimensions: None,
+            series_position: None,
+            epub_available: None,
+            pdf_available: None,
+            volume_id: None,
+            self_link: None,
+            language: None,
+            categories: vec![],
+            ratings_count: None,
+            average_rating: None,
+            description: None,
+            image_link: None,
+        }
+    }
+}
+
 impl Display for GBook {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_dates(f, DateDisplay::Raw, Locale::default())
+    }
+}
+
+impl DisplayDates for GBook {
+    fn fmt_dates(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        dates: DateDisplay,
+        locale: Locale,
+    ) -> std::fmt::Result {
         write!(
             f,
             "{title} by {authors}",
             title = self.title,
             authors = self.authors.join(", "),
         )?;
-        match (&self.publisher, &self.published_date) {
+        let date = self
+            .published_date
+            .as_deref()
+            .map(|date| dates.format(date, locale));
+        match (&self.publisher, date) {
             (Some(publisher), Some(date)) => write!(f, " ({}, {})", publisher, date)?,
             (Some(publisher), None) => write!(f, " ({})", publisher)?,
             (None, Some(date)) => write!(f, " ({})", date)?,
@@ -39,16 +195,66 @@ impl Display for GBook {
         if let Some(isbn) = &self.isbn {
             write!(f, " ({})", isbn)?;
         }
+        if let Some(page_count) = self.page_count {
+            write!(f, " ({} pages)", locale.format_number(page_count as usize))?;
+        }
         Ok(())
     }
 }
 
 impl GBooks {
-    pub fn new(api_key: String) -> Self {
-        Self {
+    /// `identifier_preference` lists industry identifier types (e.g. `ISBN_13`, `ISBN_10`, `ISSN`)
+    /// in the order they should be picked as a book's ISBN.
+    pub fn new(api_key: String, identifier_preference: Vec<String>) -> Result<Self> {
+        let api_key = api_key.trim().to_string();
+        if api_key.is_empty() {
+            return Err(miette!(
+                "The Google Books API key is empty; set `google-books-api-key` in the config file"
+            ));
+        }
+
+        Ok(Self {
             api_key,
+            identifier_preference,
             client: Client::new(),
-        }
+            user_agent: None,
+            concurrency: DEFAULT_SEARCH_CONCURRENCY,
+            verbose: false,
+        })
+    }
+
+    /// Identify ourselves with this User-Agent header in all requests.
+    pub fn with_user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    /// Fetch the details of at most `concurrency` search results at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Report requests that are retried on stderr.
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Make a minimal request to check that the API key is accepted.
+    pub async fn check(&self) -> Result<()> {
+        self.request(
+            Method::GET,
+            "/volumes",
+            |url| {
+                url.append_pair("q", "isbn:9780441013593")
+                    .append_pair("maxResults", "1");
+            },
+            |req| req,
+        )
+        .await
+        .wrap_err("Google Books API check failed")?;
+        Ok(())
     }
 
     async fn request<U, R>(&self, method: Method, endpoint: &str, u: U, r: R) -> Result<Value>
@@ -66,7 +272,10 @@ impl GBooks {
             url
         };
 
-        let default_request = self.client.request(method, url);
+        let mut default_request = self.client.request(method, url);
+        if let Some(user_agent) = &self.user_agent {
+            default_request = default_request.header("User-Agent", user_agent);
+        }
         let request = r(default_request);
 
         let response = request
@@ -83,13 +292,25 @@ impl GBooks {
             .wrap_err("Failed to read GBooks API response")?;
 
         if !status.is_success() {
+            if is_invalid_api_key_error(&response_body) {
+                return Err(miette!(
+                    "Google Books rejected the API key; check `google-books-api-key` in the config file"
+                ));
+            }
             return Err(miette!("Error {}:\n{:#?}", status, response_body));
         }
 
         Ok(response_body)
     }
 
-    pub async fn search(&self, query: &str) -> Result<impl Iterator<Item = GBook>> {
+    /// Search for books. `progress` is called with the number of results whose details have been
+    /// retrieved so far and the total number of results.
+    pub async fn search(
+        &self,
+        query: &str,
+        filter: Option<SearchFilter>,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<SearchResults> {
         let response = self
             .request(
                 Method::GET,
@@ -97,74 +318,270 @@ impl GBooks {
                 |url| {
                     url.append_pair("projection", "lite")
                         .append_pair("q", query);
+                    if let Some(filter) = filter {
+                        url.append_pair("filter", filter.as_param());
+                    }
                 },
                 |req| req,
             )
             .await
             .wrap_err("Failed to search on Google Books")?;
 
-        let volumes = future::try_join_all(
-            response["items"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|item| item["id"].as_str().unwrap().to_string())
-                .map(|id| self.get(id)),
+        // If there are no results at all, there is no `items` array.
+        let ids = response["items"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|item| item["id"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        let volumes = fetch_in_order(
+            ids,
+            self.concurrency,
+            |id| async move {
+                self.get(id.clone()).await.wrap_err_with(|| {
+                    format!("Failed to retrieve details for Google Books volume {}", id)
+                })
+            },
+            progress,
         )
-        .await
-        .wrap_err("Failed to retrieve detailed Google Books search result information")?;
-
-        Ok(volumes.into_iter().map(|volume| {
-            let isbn = volume.volume_info.get_isbn();
-            GBook {
-                title: volume.volume_info.title,
-                authors: volume.volume_info.authors.unwrap_or_default(),
-                publisher: volume.volume_info.publisher,
-                published_date: volume.volume_info.published_date,
-                description: volume.volume_info.description,
-                isbn,
-                image_link: volume
-                    .volume_info
-                    .image_links
-                    .and_then(|links| links.into_largest_image()),
+        .await;
+
+        let mut results = SearchResults {
+            books: Vec::new(),
+            total: response["totalItems"].as_u64().unwrap_or_default() as usize,
+            failed: Vec::new(),
+        };
+        for volume in volumes {
+            match volume {
+                Ok(volume) => results
+                    .books
+                    .push(volume.into_gbook(&self.identifier_preference)),
+                Err(e) => results.failed.push(e),
             }
-        }))
+        }
+
+        results.books = dedupe(results.books);
+
+        Ok(results)
+    }
+
+    /// Get a single volume by its ID, or by a link to it like
+    /// `https://books.google.com/books?id=B1hSG45JCX4C`.
+    pub async fn volume(&self, id_or_link: &str) -> Result<GBook> {
+        let id = parse_volume_id(id_or_link)
+            .ok_or_else(|| miette!("Not a Google Books volume ID or link: {}", id_or_link))?;
+        let volume = self.get(id).await?;
+        Ok(volume.into_gbook(&self.identifier_preference))
     }
 
     async fn get(&self, id: String) -> Result<SearchResult> {
-        let response = self
-            .request(
-                Method::GET,
-                &format!("/volumes/{}", id),
-                |_url| (),
-                |req| req,
-            )
-            .await?;
+        let endpoint = format!("/volumes/{}", id);
+        get_with_retry(
+            || self.request(Method::GET, &endpoint, |_url| (), |req| req),
+            |error| {
+                if self.verbose {
+                    eprintln!("Retrying Google Books volume {id} after: {error:?}");
+                }
+            },
+        )
+        .await
+    }
+}
 
-        Ok(serde_json::from_value(response)
+/// Fetch a volume with `fetch` and deserialize it. Google Books occasionally sends a partial
+/// volume, which is usually complete when requested again, so if deserializing fails, `on_retry` is
+/// called with the error and the volume is fetched once more. Failed requests aren't retried.
+async fn get_with_retry<Fut>(
+    mut fetch: impl FnMut() -> Fut,
+    on_retry: impl FnOnce(&miette::Error),
+) -> Result<SearchResult>
+where
+    Fut: Future<Output = Result<Value>>,
+{
+    let parse = |response| {
+        serde_json::from_value::<SearchResult>(response)
             .into_diagnostic()
-            .wrap_err("Failed to deserialized GBooks API response")?)
+            .wrap_err("Failed to deserialize GBooks API response")
+    };
+    match parse(fetch().await?) {
+        Ok(volume) => Ok(volume),
+        Err(error) => {
+            on_retry(&error);
+            parse(fetch().await?)
+        }
+    }
+}
+
+/// The volume ID in `input`, which is either the ID itself or a link to the volume: On Google Books
+/// (`https://books.google.com/books?id=...`, or `.../books/about/Title.html?id=...`) or in the API
+/// (`https://www.googleapis.com/books/v1/volumes/...`).
+fn parse_volume_id(input: &str) -> Option<String> {
+    let input = input.trim();
+    let is_id = |id: &str| {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    if is_id(input) {
+        return Some(input.to_string());
     }
+
+    let url = Url::parse(input).ok()?;
+    let id = match url.query_pairs().find(|(key, _)| key == "id") {
+        Some((_, id)) => id.into_owned(),
+        None => {
+            let mut segments = url.path_segments()?;
+            segments.find(|segment| *segment == "volumes")?;
+            segments.next()?.to_string()
+        }
+    };
+    is_id(&id).then_some(id)
+}
+
+fn is_invalid_api_key_error(response_body: &Value) -> bool {
+    let error = &response_body["error"];
+    let has_reason = error["details"]
+        .as_array()
+        .into_iter()
+        .chain(error["errors"].as_array())
+        .flatten()
+        .any(|detail| detail["reason"] == "API_KEY_INVALID" || detail["reason"] == "keyInvalid");
+    let has_message = error["message"]
+        .as_str()
+        .is_some_and(|msg| msg.contains("API key not valid"));
+    has_reason || has_message
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct SearchResult {
+    id: Option<String>,
+    self_link: Option<String>,
     volume_info: VolumeInfo,
+    #[serde(default)]
+    sale_info: SaleInfo,
+    #[serde(default)]
+    access_info: AccessInfo,
+}
+
+impl SearchResult {
+    fn into_gbook(self, identifier_preference: &[String]) -> GBook {
+        let format = derive_format(&self.volume_info, &self.sale_info, &self.access_info);
+        GBook {
+            format,
+            epub_available: self.access_info.epub.and_then(|epub| epub.is_available),
+            pdf_available: self.access_info.pdf.and_then(|pdf| pdf.is_available),
+            volume_id: self.id,
+            self_link: self.self_link,
+            ..self.volume_info.into_gbook(identifier_preference)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct SaleInfo {
+    #[serde(default)]
+    is_ebook: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct AccessInfo {
+    epub: Option<Availability>,
+    pdf: Option<Availability>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Availability {
+    is_available: Option<bool>,
+}
+
+/// Make a best guess at the format of a volume. Google Books doesn't tell hardcovers and
+/// paperbacks apart, so this only ever recognizes ebooks, and only if the volume is a book that
+/// is actually sold as an ebook in a downloadable format.
+fn derive_format(
+    info: &VolumeInfo,
+    sale_info: &SaleInfo,
+    access_info: &AccessInfo,
+) -> Option<Format> {
+    let is_book = info.print_type.as_deref().is_none_or(|ty| ty == "BOOK");
+    let available = |availability: &Option<Availability>| {
+        availability.as_ref().and_then(|a| a.is_available) == Some(true)
+    };
+    let downloadable = available(&access_info.epub) || available(&access_info.pdf);
+    (is_book && sale_info.is_ebook && downloadable).then_some(Format::Ebook)
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct VolumeInfo {
     title: String,
+    subtitle: Option<String>,
     authors: Option<Vec<String>>,
     publisher: Option<String>,
     published_date: Option<String>,
     description: Option<String>,
+    page_count: Option<u32>,
+    print_type: Option<String>,
+    language: Option<String>,
+    categories: Option<Vec<String>>,
+    ratings_count: Option<u32>,
+    average_rating: Option<f64>,
+    dimensions: Option<Dimensions>,
+    series_info: Option<SeriesInfo>,
     industry_identifiers: Option<Vec<IndustryIdentifier>>,
     image_links: Option<ImageLinks>,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SeriesInfo {
+    book_display_number: Option<String>,
+}
+
+/// Parse the position of a volume within its series. These are usually plain numbers like "3" or
+/// "2.5", but volumes collecting several books have ranges like "1-2", for which the first number
+/// is used.
+fn parse_series_position(number: &str) -> Option<f64> {
+    let number = number.trim();
+    let end = number
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(number.len());
+    number[..end].parse().ok()
+}
+
+/// Each dimension is a string including the unit, like "24.00 cm".
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Dimensions {
+    height: Option<String>,
+    width: Option<String>,
+    thickness: Option<String>,
+}
+
+impl Dimensions {
+    fn summary(&self) -> Option<String> {
+        let parts = [
+            ("height", &self.height),
+            ("width", &self.width),
+            ("thickness", &self.thickness),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let value = value.as_deref()?.trim();
+            (!value.is_empty()).then(|| format!("{name} {value}"))
+        })
+        .collect::<Vec<_>>();
+
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct ImageLinks {
@@ -184,17 +601,310 @@ struct IndustryIdentifier {
     identifier: String,
 }
 
-impl VolumeInfo {
-    fn get_isbn(&self) -> Option<String> {
-        if let Some(ids) = &self.industry_identifiers {
-            for id in ids {
-                if id.ty == "ISBN_13" {
-                    return Some(id.identifier.clone());
+/// Run `fetch` for each of `ids`, at most `concurrency` at once, and return the results in the order
+/// of `ids` no matter in which order they finish. `progress` is called with the number of finished
+/// fetches and the total after each one.
+async fn fetch_in_order<T, Fut>(
+    ids: Vec<String>,
+    concurrency: usize,
+    fetch: impl Fn(String) -> Fut,
+    progress: impl FnMut(usize, usize),
+) -> Vec<T>
+where
+    Fut: Future<Output = T>,
+{
+    let total = ids.len();
+    let done = Cell::new(0);
+    let progress = RefCell::new(progress);
+
+    stream::iter(ids)
+        .map(|id| {
+            let (done, progress) = (&done, &progress);
+            let fetched = fetch(id);
+            async move {
+                let result = fetched.await;
+                done.set(done.get() + 1);
+                (progress.borrow_mut())(done.get(), total);
+                result
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Remove near-duplicate volumes, i.e. those with the same ISBN or, lacking one, the same title and
+/// authors. Of each set of duplicates, the one with the most complete metadata is kept, in the
+/// position of the first of them.
+fn dedupe(books: Vec<GBook>) -> Vec<GBook> {
+    fn normalize(s: &str) -> String {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn key(book: &GBook) -> String {
+        match &book.isbn {
+            Some(isbn) => format!("isbn:{}", isbn),
+            None => format!(
+                "{}|{}",
+                normalize(&book.title),
+                book.authors
+                    .iter()
+                    .map(|author| normalize(author))
+                    .collect::<Vec<_>>()
+                    .join("|")
+            ),
+        }
+    }
+
+    // Covers and descriptions are the most visible in Notion, so they win over everything else.
+    fn completeness(book: &GBook) -> (bool, bool, usize) {
+        let other_fields = [
+            !book.authors.is_empty(),
+            book.publisher.is_some(),
+            book.published_date.is_some(),
+            book.page_count.is_some(),
+            book.edition.is_some(),
+        ];
+        (
+            book.image_link.is_some(),
+            book.description.is_some(),
+            other_fields.iter().filter(|present| **present).count(),
+        )
+    }
+
+    let mut deduped: Vec<GBook> = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+
+    for book in books {
+        let key = key(&book);
+        match keys.iter().position(|k| *k == key) {
+            Some(i) => {
+                if completeness(&book) > completeness(&deduped[i]) {
+                    deduped[i] = book;
                 }
             }
+            None => {
+                keys.push(key);
+                deduped.push(book);
+            }
+        }
+    }
+
+    deduped
+}
+
+// Google Books occasionally has stray whitespace around values, which would otherwise end up as
+// distinct select options in Notion.
+fn trim(s: String) -> String {
+    s.trim().to_string()
+}
+
+fn trim_opt(s: Option<String>) -> Option<String> {
+    s.map(trim).filter(|s| !s.is_empty())
+}
+
+impl VolumeInfo {
+    fn into_gbook(self, identifier_preference: &[String]) -> GBook {
+        let isbn = trim_opt(self.get_isbn(identifier_preference));
+        let identifiers = self.get_identifiers(identifier_preference);
+        let edition = self.get_edition();
+        let original_year = self.get_original_year();
+        GBook {
+            title: trim(self.title),
+            subtitle: trim_opt(self.subtitle),
+            authors: self
+                .authors
+                .unwrap_or_default()
+                .into_iter()
+                .map(trim)
+                .filter(|author| !author.is_empty())
+                .collect(),
+            publisher: trim_opt(self.publisher),
+            published_date: trim_opt(self.published_date),
+            description: self.description,
+            isbn,
+            identifiers,
+            page_count: self.page_count,
+            edition,
+            original_year,
+            format: None,
+            dimensions: self.dimensions.as_ref().and_then(Dimensions::summary),
+            epub_available: None,
+            pdf_available: None,
+            volume_id: None,
+            self_link: None,
+            language: trim_opt(self.language),
+            categories: self
+                .categories
+                .unwrap_or_default()
+                .into_iter()
+                .map(trim)
+                .filter(|category| !category.is_empty())
+                .collect(),
+            ratings_count: self.ratings_count,
+            average_rating: self.average_rating,
+            series_position: self
+                .series_info
+                .and_then(|info| info.book_display_number)
+                .as_deref()
+                .and_then(parse_series_position),
+            image_link: self
+                .image_links
+                .and_then(|links| links.into_largest_image()),
+        }
+    }
+
+    fn get_edition(&self) -> Option<String> {
+        // Descriptions frequently mention *other* editions ("the first edition sold a million
+        // copies"), so only trust them when they're talking about "this" edition.
+        self.subtitle
+            .as_deref()
+            .and_then(|subtitle| extract_edition(subtitle, false))
+            .or_else(|| extract_edition(&self.title, false))
+            .or_else(|| {
+                self.description
+                    .as_deref()
+                    .and_then(|description| extract_edition(description, true))
+            })
+    }
+
+    fn get_original_year(&self) -> Option<u16> {
+        let year = extract_original_year(&strip_tags(self.description.as_deref()?))?;
+        // A reprint can't be older than the original, so this must have been about something else.
+        let published = self
+            .published_date
+            .as_deref()
+            .and_then(PublishedDate::parse);
+        match published {
+            Some(published) if published.year < year => None,
+            _ => Some(year),
         }
-        None
     }
+
+    fn get_identifiers(&self, identifier_preference: &[String]) -> Vec<(String, String)> {
+        let mut identifiers = self
+            .industry_identifiers
+            .iter()
+            .flatten()
+            .map(|id| (id.ty.clone(), id.identifier.trim().to_string()))
+            .filter(|(_, identifier)| !identifier.is_empty())
+            .collect::<Vec<_>>();
+        // Types that aren't preferred at all go last, in their original order.
+        identifiers.sort_by_key(|(ty, _)| {
+            identifier_preference
+                .iter()
+                .position(|preferred| preferred == ty)
+                .unwrap_or(identifier_preference.len())
+        });
+        identifiers
+    }
+
+    fn get_isbn(&self, identifier_preference: &[String]) -> Option<String> {
+        let ids = self.industry_identifiers.as_ref()?;
+        identifier_preference.iter().find_map(|preferred| {
+            ids.iter()
+                .find(|id| &id.ty == preferred)
+                .map(|id| id.identifier.clone())
+        })
+    }
+}
+
+const ORDINAL_WORDS: [&str; 20] = [
+    "first",
+    "second",
+    "third",
+    "fourth",
+    "fifth",
+    "sixth",
+    "seventh",
+    "eighth",
+    "ninth",
+    "tenth",
+    "eleventh",
+    "twelfth",
+    "thirteenth",
+    "fourteenth",
+    "fifteenth",
+    "sixteenth",
+    "seventeenth",
+    "eighteenth",
+    "nineteenth",
+    "twentieth",
+];
+
+/// Parse an ordinal like "3rd" or "third".
+fn parse_ordinal(word: &str) -> Option<u32> {
+    if let Some(pos) = ORDINAL_WORDS.iter().position(|w| *w == word) {
+        return Some(pos as u32 + 1);
+    }
+
+    let digits_end = word.find(|c: char| !c.is_ascii_digit())?;
+    let n = word[..digits_end].parse::<u32>().ok()?;
+    (n > 0 && word[digits_end..] == *ordinal_suffix(n)).then_some(n)
+}
+
+fn ordinal_suffix(n: u32) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+/// Look for an edition like "3rd Edition", "Third Edition" or "2nd ed." in `text`, returning it
+/// normalized to e.g. "3rd Edition". If `require_this` is set, only accept editions preceded by
+/// "this" ("This third edition ...").
+pub fn extract_edition(text: &str, require_this: bool) -> Option<String> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+
+    words.windows(2).enumerate().find_map(|(i, pair)| {
+        if pair[1] != "edition" && pair[1] != "ed" {
+            return None;
+        }
+        if require_this && (i == 0 || words[i - 1] != "this") {
+            return None;
+        }
+        let n = parse_ordinal(&pair[0])?;
+        Some(format!("{}{} Edition", n, ordinal_suffix(n)))
+    })
+}
+
+/// Look for the year a book was originally published in `text`, like "Originally published in
+/// 1965" or "First published 1965". Descriptions rarely say this, and mention plenty of other
+/// years, so only a year right after one of these phrases is accepted.
+pub fn extract_original_year(text: &str) -> Option<u16> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+
+    words.windows(2).enumerate().find_map(|(i, pair)| {
+        if (pair[0] != "originally" && pair[0] != "first") || pair[1] != "published" {
+            return None;
+        }
+        let mut rest = words[i + 2..].iter();
+        let mut year = rest.next()?;
+        if year == "in" {
+            year = rest.next()?;
+        }
+        if year.len() != 4 {
+            return None;
+        }
+        year.parse()
+            .ok()
+            .filter(|year| (1000..=9999).contains(year))
+    })
 }
 
 impl ImageLinks {
@@ -207,3 +917,460 @@ impl ImageLinks {
             .or(self.small_thumbnail)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use serde_json::json;
+
+    #[test]
+    fn empty_api_key_is_rejected() {
+        assert!(GBooks::new("".to_string(), vec![]).is_err());
+        assert!(GBooks::new("   ".to_string(), vec![]).is_err());
+    }
+
+    #[test]
+    fn volume_ids_are_parsed_from_links() {
+        let cases = [
+            ("B1hSG45JCX4C", Some("B1hSG45JCX4C")),
+            (" B1hSG45JCX4C\n", Some("B1hSG45JCX4C")),
+            (
+                "https://books.google.com/books?id=B1hSG45JCX4C&printsec=frontcover",
+                Some("B1hSG45JCX4C"),
+            ),
+            (
+                "https://books.google.de/books/about/Dune.html?id=B1hSG45JCX4C&redir_esc=y",
+                Some("B1hSG45JCX4C"),
+            ),
+            (
+                "https://www.googleapis.com/books/v1/volumes/B1hSG45JCX4C",
+                Some("B1hSG45JCX4C"),
+            ),
+            ("https://books.google.com/books", None),
+            ("not an id", None),
+        ];
+        for (input, id) in cases {
+            assert_eq!(parse_volume_id(input).as_deref(), id, "{input}");
+        }
+    }
+
+    #[test]
+    fn detect_invalid_api_key_error() {
+        let body = json!({
+            "error": {
+                "code": 400,
+                "message": "API key not valid. Please pass a valid API key.",
+                "errors": [{ "reason": "badRequest" }],
+                "details": [{ "reason": "API_KEY_INVALID" }]
+            }
+        });
+        assert!(is_invalid_api_key_error(&body));
+
+        let body = json!({
+            "error": {
+                "code": 400,
+                "message": "Missing query.",
+                "errors": [{ "reason": "queryRequired" }]
+            }
+        });
+        assert!(!is_invalid_api_key_error(&body));
+    }
+
+    #[test]
+    fn volume_info_fields_are_trimmed() {
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": " Dune\n",
+            "authors": ["  Frank Herbert ", " "],
+            "publisher": "  Penguin ",
+            "publishedDate": "1990 ",
+            "industryIdentifiers": [{ "type": "ISBN_13", "identifier": " 9780441013593" }],
+        }))
+        .unwrap();
+        let book = info.into_gbook(&["ISBN_13".to_string()]);
+
+        assert_eq!(book.title, "Dune");
+        assert_eq!(book.authors, ["Frank Herbert"]);
+        assert_eq!(book.publisher.as_deref(), Some("Penguin"));
+        assert_eq!(book.published_date.as_deref(), Some("1990"));
+        assert_eq!(book.isbn.as_deref(), Some("9780441013593"));
+    }
+
+    fn volume_with_identifiers() -> VolumeInfo {
+        serde_json::from_value(json!({
+            "title": "Dune",
+            "industryIdentifiers": [
+                { "type": "ISBN_10", "identifier": "0441013597" },
+                { "type": "ISBN_13", "identifier": "9780441013593" },
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn identifier_preference_defaults_to_isbn_13() {
+        let config = crate::parse_config(
+            "config.kdl",
+            "google-books-api-key \"key\"\nnotion { integration-token \"token\"; }",
+        )
+        .unwrap();
+        let info = volume_with_identifiers();
+        assert_eq!(
+            info.get_isbn(&config.identifier_preference).as_deref(),
+            Some("9780441013593")
+        );
+    }
+
+    #[test]
+    fn identifiers_are_ordered_by_preference() {
+        let info = volume_with_identifiers();
+        assert_eq!(
+            info.get_identifiers(&["ISBN_13".to_string()]),
+            [
+                ("ISBN_13".to_string(), "9780441013593".to_string()),
+                ("ISBN_10".to_string(), "0441013597".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifier_preference_isbn_10_first() {
+        let info = volume_with_identifiers();
+        assert_eq!(
+            info.get_isbn(&["ISBN_10".to_string(), "ISBN_13".to_string()])
+                .as_deref(),
+            Some("0441013597")
+        );
+        assert_eq!(info.get_isbn(&["ISSN".to_string()]), None);
+    }
+
+    #[test]
+    fn edition_from_titles() {
+        let cases = [
+            (
+                "Calculus: Early Transcendentals, 8th Edition",
+                Some("8th Edition"),
+            ),
+            ("Introduction to Algorithms, 3rd ed.", Some("3rd Edition")),
+            (
+                "Operating System Concepts (Tenth Edition)",
+                Some("10th Edition"),
+            ),
+            ("Modern Physics 21st edition", Some("21st Edition")),
+            ("The Second Coming", None),
+            ("Dune: Deluxe Edition", None),
+            ("The 2nd Amendment", None),
+            ("Harry Potter and the Philosopher's Stone", None),
+        ];
+        for (title, edition) in cases {
+            assert_eq!(extract_edition(title, false).as_deref(), edition, "{title}");
+        }
+    }
+
+    #[test]
+    fn edition_from_description_requires_this() {
+        assert_eq!(
+            extract_edition("The first edition sold a million copies.", true),
+            None
+        );
+        assert_eq!(
+            extract_edition("This fourth edition adds two new chapters.", true).as_deref(),
+            Some("4th Edition")
+        );
+    }
+
+    #[test]
+    fn original_year_from_description() {
+        let cases = [
+            ("Originally published in 1965.", Some(1965)),
+            ("First published 1949 by Secker & Warburg.", Some(1949)),
+            ("Published in 1965 and never out of print.", None),
+            ("First published in Great Britain in 1965.", None),
+            ("Originally published in 12 parts.", None),
+            ("Over 1,000,000 copies sold since 1965.", None),
+        ];
+        for (description, year) in cases {
+            assert_eq!(extract_original_year(description), year, "{description}");
+        }
+    }
+
+    #[test]
+    fn original_year_must_precede_publication() {
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Dune",
+            "publishedDate": "2005-08-02",
+            "description": "<p>Originally published in <b>1965</b>, Dune is ...</p>",
+        }))
+        .unwrap();
+        assert_eq!(info.get_original_year(), Some(1965));
+
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Dune",
+            "publishedDate": "1965",
+            "description": "First published 2005 as part of the anniversary series.",
+        }))
+        .unwrap();
+        assert_eq!(info.get_original_year(), None);
+    }
+
+    #[test]
+    fn edition_prefers_subtitle() {
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Compilers",
+            "subtitle": "Principles, Techniques, and Tools (Second Edition)",
+            "description": "This first edition is a classic.",
+        }))
+        .unwrap();
+        assert_eq!(info.get_edition().as_deref(), Some("2nd Edition"));
+    }
+
+    fn book(title: &str, isbn: Option<&str>) -> GBook {
+        GBook {
+            authors: vec!["Frank Herbert".to_string()],
+            isbn: isbn.map(str::to_string),
+            ..GBook::new(title)
+        }
+    }
+
+    #[test]
+    fn series_position_is_parsed() {
+        assert_eq!(parse_series_position("3"), Some(3.0));
+        assert_eq!(parse_series_position(" 2.5 "), Some(2.5));
+        assert_eq!(parse_series_position("1-2"), Some(1.0));
+        assert_eq!(parse_series_position("Book 3"), None);
+        assert_eq!(parse_series_position(""), None);
+
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Dune",
+            "seriesInfo": {
+                "kind": "books#volume_series_info",
+                "bookDisplayNumber": "1",
+                "volumeSeries": [{ "seriesId": "abc", "orderNumber": 1 }]
+            }
+        }))
+        .unwrap();
+        assert_eq!(info.into_gbook(&[]).series_position, Some(1.0));
+
+        let info: VolumeInfo = serde_json::from_value(json!({ "title": "Dune" })).unwrap();
+        assert_eq!(info.into_gbook(&[]).series_position, None);
+    }
+
+    #[test]
+    fn ratings_are_read() {
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Dune",
+            "averageRating": 4.5,
+            "ratingsCount": 1234
+        }))
+        .unwrap();
+        let book = info.into_gbook(&[]);
+        assert_eq!(book.ratings_count, Some(1234));
+        assert_eq!(book.average_rating, Some(4.5));
+    }
+
+    #[test]
+    fn dimensions_summary() {
+        let info: VolumeInfo = serde_json::from_value(json!({
+            "title": "Dune",
+            "dimensions": { "height": "24.00 cm", "thickness": " 3.00 cm " },
+        }))
+        .unwrap();
+        assert_eq!(
+            info.into_gbook(&[]).dimensions.as_deref(),
+            Some("height 24.00 cm, thickness 3.00 cm")
+        );
+
+        let info: VolumeInfo =
+            serde_json::from_value(json!({ "title": "Dune", "dimensions": {} })).unwrap();
+        assert_eq!(info.into_gbook(&[]).dimensions, None);
+    }
+
+    #[test]
+    fn volume_id_and_self_link_are_read() {
+        let result: SearchResult = serde_json::from_value(json!({
+            "id": "B1hSG45JCX4C",
+            "selfLink": "https://www.googleapis.com/books/v1/volumes/B1hSG45JCX4C",
+            "volumeInfo": { "title": "Dune" },
+        }))
+        .unwrap();
+        let book = result.into_gbook(&[]);
+        assert_eq!(book.volume_id.as_deref(), Some("B1hSG45JCX4C"));
+        assert_eq!(
+            book.self_link.as_deref(),
+            Some("https://www.googleapis.com/books/v1/volumes/B1hSG45JCX4C")
+        );
+    }
+
+    #[test]
+    fn download_availability_is_read() {
+        let result = |value: Value| -> GBook {
+            serde_json::from_value::<SearchResult>(value)
+                .unwrap()
+                .into_gbook(&[])
+        };
+
+        let book = result(json!({
+            "volumeInfo": { "title": "Dune" },
+            "accessInfo": { "epub": { "isAvailable": true }, "pdf": { "isAvailable": false } },
+        }));
+        assert_eq!(book.epub_available, Some(true));
+        assert_eq!(book.pdf_available, Some(false));
+
+        let book = result(json!({
+            "volumeInfo": { "title": "Dune" },
+            "accessInfo": { "epub": {} },
+        }));
+        assert_eq!(book.epub_available, None);
+        assert_eq!(book.pdf_available, None);
+
+        let book = result(json!({ "volumeInfo": { "title": "Dune" } }));
+        assert_eq!(book.epub_available, None);
+    }
+
+    #[test]
+    fn format_is_derived_conservatively() {
+        let result = |value: Value| -> SearchResult { serde_json::from_value(value).unwrap() };
+        let format = |result: SearchResult| {
+            derive_format(&result.volume_info, &result.sale_info, &result.access_info)
+        };
+
+        let ebook = json!({
+            "volumeInfo": { "title": "Dune", "printType": "BOOK" },
+            "saleInfo": { "isEbook": true },
+            "accessInfo": { "epub": { "isAvailable": true }, "pdf": { "isAvailable": false } },
+        });
+        assert_eq!(format(result(ebook.clone())), Some(Format::Ebook));
+
+        let mut not_downloadable = ebook.clone();
+        not_downloadable["accessInfo"]["epub"]["isAvailable"] = json!(false);
+        assert_eq!(format(result(not_downloadable)), None);
+
+        let mut magazine = ebook.clone();
+        magazine["volumeInfo"]["printType"] = json!("MAGAZINE");
+        assert_eq!(format(result(magazine)), None);
+
+        let print = json!({
+            "volumeInfo": { "title": "Dune", "printType": "BOOK" },
+            "saleInfo": { "isEbook": false },
+        });
+        assert_eq!(format(result(print)), None);
+        assert_eq!(
+            format(result(json!({ "volumeInfo": { "title": "Dune" } }))),
+            None
+        );
+    }
+
+    #[test]
+    fn dedupe_by_isbn_prefers_cover() {
+        let mut with_cover = book("Dune", Some("9780441013593"));
+        with_cover.image_link = Some("https://example.com/cover.jpg".to_string());
+        let mut with_publisher = book("Dune", Some("9780441013593"));
+        with_publisher.publisher = Some("Ace".to_string());
+
+        let books = vec![
+            with_publisher,
+            book("Dune Messiah", Some("9780593098233")),
+            with_cover,
+        ];
+        let deduped = dedupe(books);
+
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0].title, "Dune");
+        assert!(deduped[0].image_link.is_some());
+        assert_eq!(deduped[1].title, "Dune Messiah");
+    }
+
+    #[test]
+    fn dedupe_by_normalized_title_and_authors() {
+        let mut more_complete = book("dune!", None);
+        more_complete.publisher = Some("Ace".to_string());
+        let books = vec![book("Dune", None), more_complete, book("Dune", Some("123"))];
+        let deduped = dedupe(books);
+
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0].publisher.as_deref(), Some("Ace"));
+        assert_eq!(deduped[1].isbn.as_deref(), Some("123"));
+    }
+
+    #[tokio::test]
+    async fn partial_volumes_are_fetched_again() {
+        let mut responses = vec![
+            Ok(json!({ "id": "abc", "volumeInfo": { "title": "Dune" } })),
+            Ok(json!({ "id": "abc", "volumeInfo": {} })),
+        ];
+        let mut retries = 0;
+        let volume = get_with_retry(|| future::ready(responses.pop().unwrap()), |_| retries += 1)
+            .await
+            .unwrap();
+        assert_eq!(volume.volume_info.title, "Dune");
+        assert_eq!(retries, 1);
+        assert!(responses.is_empty());
+
+        // Only once, though.
+        let mut responses = vec![Ok(json!({})), Ok(json!({}))];
+        let result = get_with_retry(|| future::ready(responses.pop().unwrap()), |_| ()).await;
+        assert!(result.is_err());
+        assert!(responses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_not_fetched_again() {
+        let mut responses = vec![
+            Ok(json!({ "id": "abc", "volumeInfo": { "title": "Dune" } })),
+            Err(miette!("Error 503")),
+        ];
+        let result = get_with_retry(
+            || future::ready(responses.pop().unwrap()),
+            |_| panic!("retried"),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(responses.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn details_are_fetched_in_order_with_bounded_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let ids = ["a", "b", "c", "d", "e"].map(str::to_string).to_vec();
+        let mut reported = Vec::new();
+
+        let results = fetch_in_order(
+            ids,
+            2,
+            |id| {
+                let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    // Earlier IDs take longer, so they finish out of order.
+                    let delay = if id == "a" { 30 } else { 5 };
+                    tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    if id == "c" {
+                        Err(id)
+                    } else {
+                        Ok(id)
+                    }
+                }
+            },
+            |done, total| reported.push((done, total)),
+        )
+        .await;
+
+        assert_eq!(
+            results,
+            [
+                Ok("a".to_string()),
+                Ok("b".to_string()),
+                Err("c".to_string()),
+                Ok("d".to_string()),
+                Ok("e".to_string()),
+            ]
+        );
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(reported.last(), Some(&(5, 5)));
+    }
+}
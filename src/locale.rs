@@ -0,0 +1,139 @@
+// How dates and numbers are written in output meant for people, like search results and summaries.
+// Everything sent to Notion stays in its canonical format regardless of the locale.
+
+use miette::{miette, Result};
+
+use crate::dates::PublishedDate;
+
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const GERMAN_MONTHS: [&str; 12] = [
+    "Januar",
+    "Februar",
+    "März",
+    "April",
+    "Mai",
+    "Juni",
+    "Juli",
+    "August",
+    "September",
+    "Oktober",
+    "November",
+    "Dezember",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Locale {
+    /// "August 1, 1965" and "1,234".
+    EnUs,
+    /// "1 August 1965" and "1,234".
+    #[default]
+    EnGb,
+    /// "1. August 1965" and "1.234".
+    De,
+}
+
+impl Locale {
+    /// Parse a locale like "en-US", "en_GB" or "de".
+    pub fn parse(tag: &str) -> Result<Self> {
+        match tag.trim().to_lowercase().replace('_', "-").as_str() {
+            "en-us" => Ok(Locale::EnUs),
+            "en-gb" | "en" => Ok(Locale::EnGb),
+            "de" | "de-de" | "de-at" => Ok(Locale::De),
+            _ => Err(miette!(
+                "Unsupported locale '{}'; supported are en-US, en-GB and de-DE",
+                tag
+            )),
+        }
+    }
+
+    /// Write out `date` with its month name, as far as it is known.
+    pub fn format_date(self, date: &PublishedDate) -> String {
+        let year = date.year;
+        let month = match date.month {
+            Some(month) => self.month_name(month),
+            None => return year.to_string(),
+        };
+        match (self, date.day) {
+            (_, None) => format!("{month} {year}"),
+            (Locale::EnUs, Some(day)) => format!("{month} {day}, {year}"),
+            (Locale::EnGb, Some(day)) => format!("{day} {month} {year}"),
+            (Locale::De, Some(day)) => format!("{day}. {month} {year}"),
+        }
+    }
+
+    fn month_name(self, month: u8) -> &'static str {
+        let names = match self {
+            Locale::EnUs | Locale::EnGb => &ENGLISH_MONTHS,
+            Locale::De => &GERMAN_MONTHS,
+        };
+        names[month as usize - 1]
+    }
+
+    /// Write out a count with its thousands grouped, like "12,345".
+    pub fn format_number(self, n: usize) -> String {
+        let separator = match self {
+            Locale::EnUs | Locale::EnGb => ',',
+            Locale::De => '.',
+        };
+        let digits = n.to_string();
+        let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                formatted.push(separator);
+            }
+            formatted.push(digit);
+        }
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_are_parsed() {
+        assert_eq!(Locale::parse("en-US").unwrap(), Locale::EnUs);
+        assert_eq!(Locale::parse("en_GB").unwrap(), Locale::EnGb);
+        assert_eq!(Locale::parse(" de-DE ").unwrap(), Locale::De);
+        assert!(Locale::parse("fr-FR").is_err());
+    }
+
+    #[test]
+    fn dates_are_localized() {
+        let date = PublishedDate::parse("1965-03-01").unwrap();
+        assert_eq!(Locale::EnUs.format_date(&date), "March 1, 1965");
+        assert_eq!(Locale::EnGb.format_date(&date), "1 March 1965");
+        assert_eq!(Locale::De.format_date(&date), "1. März 1965");
+
+        let month = PublishedDate::parse("1965-03").unwrap();
+        assert_eq!(Locale::EnUs.format_date(&month), "March 1965");
+        assert_eq!(Locale::De.format_date(&month), "März 1965");
+
+        let year = PublishedDate::parse("1965").unwrap();
+        assert_eq!(Locale::De.format_date(&year), "1965");
+    }
+
+    #[test]
+    fn numbers_are_grouped() {
+        assert_eq!(Locale::EnUs.format_number(0), "0");
+        assert_eq!(Locale::EnUs.format_number(999), "999");
+        assert_eq!(Locale::EnUs.format_number(1234), "1,234");
+        assert_eq!(Locale::EnGb.format_number(1234567), "1,234,567");
+        assert_eq!(Locale::De.format_number(123456), "123.456");
+    }
+}
@@ -1,198 +1,1638 @@
 #![feature(iterator_try_collect)]
-#![feature(let_chains)]
 
+mod dates;
 mod descriptions;
 mod gbooks;
+mod locale;
+mod markdown;
+mod matching;
 mod notion;
+mod titles;
+mod ui;
 
 use clap::Parser;
-use descriptions::RichText;
-use miette::{Context, IntoDiagnostic, Result};
-use std::io::Write;
+use descriptions::{Block, DescriptionStyle, RichText, TextFragment, TextStyle};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
 use crate::{
-    gbooks::{GBook, GBooks},
-    notion::{Notion, NotionBookEntry},
+    dates::{DateDisplay, DisplayDates},
+    gbooks::{Format, GBook, GBooks, SearchFilter, DEFAULT_SEARCH_CONCURRENCY},
+    locale::Locale,
+    matching::{IsbnMatch, Narrowed},
+    notion::{
+        parse_notion_id, Database, DatabaseOptions, Filter, GenreMapping, IsbnSeparator, Notion,
+        NotionBookEntry, OwnershipConfig, PropertyNames, PublisherSanitization,
+        ReadingStatusConfig, DEFAULT_REQUESTS_PER_SECOND,
+    },
+    titles::TitleTemplate,
+    ui::{EndOfInput, TerminalUi, Ui},
 };
 
-#[derive(knuffel::Decode)]
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 struct Config {
     #[knuffel(child, unwrap(argument))]
     google_books_api_key: String,
+    /// Industry identifier types to use as the ISBN, most preferred first.
+    #[knuffel(child, unwrap(arguments), default = default_identifier_preference())]
+    #[serde(default = "default_identifier_preference")]
+    identifier_preference: Vec<String>,
+    /// The User-Agent header to send to Google Books and Notion. Defaults to `DEFAULT_USER_AGENT`.
+    #[knuffel(child, unwrap(argument))]
+    user_agent: Option<String>,
+    /// How many search results to fetch details for from Google Books at once.
+    #[knuffel(child, unwrap(argument))]
+    search_concurrency: Option<usize>,
+    /// How dates and numbers are written in output, like "en-US". Defaults to en-GB.
+    #[knuffel(child, unwrap(argument))]
+    locale: Option<String>,
     #[knuffel(child)]
     notion: NotionConfig,
 }
 
-#[derive(knuffel::Decode)]
+impl Config {
+    /// Apply the command line options that override the config file, and fill in the defaults of
+    /// settings that aren't set, so this is the configuration that is actually used.
+    fn resolve(mut self, args: &Args) -> Self {
+        if args.db.is_some() || args.db_name.is_some() {
+            self.notion.database_id = args.db.clone();
+            self.notion.database_name = args.db_name.clone();
+        }
+        self.user_agent
+            .get_or_insert_with(|| DEFAULT_USER_AGENT.to_string());
+        self.search_concurrency
+            .get_or_insert(DEFAULT_SEARCH_CONCURRENCY);
+        self.notion
+            .requests_per_second
+            .get_or_insert(DEFAULT_REQUESTS_PER_SECOND);
+        self
+    }
+
+    /// The configuration with the API key and integration token left out, to show it.
+    fn redacted(mut self) -> Self {
+        self.google_books_api_key = REDACTED.to_string();
+        self.notion.integration_token = REDACTED.to_string();
+        self
+    }
+}
+
+const REDACTED: &str = "<redacted>";
+
+fn default_identifier_preference() -> Vec<String> {
+    vec!["ISBN_13".to_string()]
+}
+
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 struct NotionConfig {
     #[knuffel(child, unwrap(argument))]
     integration_token: String,
     #[knuffel(child, unwrap(argument))]
-    database_id: String,
+    database_id: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    database_name: Option<String>,
+    #[knuffel(child, default)]
+    #[serde(default)]
+    properties: PropertyNames,
+    #[knuffel(child, unwrap(argument))]
+    authors_database_id: Option<String>,
+    #[knuffel(child, default)]
+    #[serde(default)]
+    ownership: OwnershipConfig,
+    #[knuffel(child, default)]
+    #[serde(default)]
+    publisher: PublisherSanitization,
+    #[knuffel(child, default)]
+    #[serde(default)]
+    genres: GenreMapping,
+    #[knuffel(child)]
+    reading_status: Option<ReadingStatusConfig>,
+    /// The maximum number of requests to send to Notion per second. Zero disables the limit.
+    #[knuffel(child, unwrap(argument))]
+    requests_per_second: Option<u32>,
+    /// How many page bodies to fetch from Notion at once when searching.
+    #[knuffel(child, unwrap(argument))]
+    description_concurrency: Option<usize>,
+    /// Write descriptions to a child page instead of the page body.
+    #[knuffel(child)]
+    #[serde(default)]
+    description_child_page: bool,
+    /// The value of the Type select to use for books.
+    #[knuffel(child, unwrap(argument))]
+    book_type: Option<String>,
+    /// Who new entries are added by, as a name or the ID of a Notion user.
+    #[knuffel(child, unwrap(argument))]
+    added_by: Option<String>,
+    /// How ISBNs are joined in the ISBNs property, "comma" or "newline". Defaults to comma.
+    #[knuffel(child, unwrap(argument))]
+    isbn_separator: Option<String>,
+    /// How the Name of new entries is made, like "{title} ({year})". Defaults to just the title.
+    #[knuffel(child, unwrap(argument))]
+    title_template: Option<String>,
 }
 
 #[derive(clap::Parser)]
-#[clap(author, version)]
+#[clap(
+    author,
+    version,
+    after_help = "To quit, enter an empty query, :q or quit, or end the input (Ctrl-D).
+
+Exit codes:
+    0    Success
+    1    An error stopped the program
+    2    Invalid arguments
+    3    Books for some of the queries given as arguments could not be added"
+)]
 struct Args {
+    /// Queries to add books for without asking, instead of reading them interactively. The first
+    /// result of each is used, and existing entries are handled according to `--on-match`.
+    queries: Vec<String>,
+    /// When adding books for queries given as arguments, skip those whose first result matches the
+    /// query worse than this (from 0 to 1: the share of query words found in the title or authors,
+    /// or 1 for a matching ISBN). Skipped books are reported for review.
+    #[clap(long, default_value = "0.5")]
+    min_match_score: f64,
+    /// When more existing entries than this match the title of a book, only list those with its
+    /// ISBN, or else those with exactly its title, or else this many of the best matches. 0 lists
+    /// all of them.
+    #[clap(long, default_value = "10")]
+    max_matches: usize,
     /// Mark all added or modified books as owned.
     #[clap(long)]
     owned: bool,
-    /// Interpret all queries as being an ISBN.
+    /// Interpret all queries as being an ISBN. Partial ISBNs (the first few digits) are searched
+    /// for as well, offering all books whose ISBN starts with them.
     #[clap(long)]
     isbn: bool,
+    /// Interpret all queries as a book title, instead of searching all fields for the words.
+    #[clap(long, conflicts_with = "isbn")]
+    title_search: bool,
+    /// If a book has several identifiers (like both an ISBN-13 and ISBN-10), ask which one to store
+    /// as the ISBN, instead of going by `identifier-preference`.
+    #[clap(long)]
+    choose_isbn: bool,
+    /// Use an OpenLibrary cover (looked up by ISBN) for books that don't have one on Google Books.
+    #[clap(long)]
+    openlibrary_covers: bool,
+    /// Only search for books with this viewability.
+    #[clap(long, arg_enum)]
+    filter: Option<SearchFilter>,
+    /// Only show search results with at least this many pages.
+    #[clap(long)]
+    min_pages: Option<u32>,
+    /// Only show search results with at most this many pages.
+    #[clap(long)]
+    max_pages: Option<u32>,
+    /// Sort search results by page count, longest first.
+    #[clap(long)]
+    sort_by_pages: bool,
+    /// How to sort search results. `popularity` puts the volumes with the most ratings on Google
+    /// Books first (then those with the best average rating), which is usually the main edition.
+    #[clap(long, arg_enum, conflicts_with = "sort-by-pages")]
+    sort: Option<SortOrder>,
+    /// Add a note to the entry. On update, the note is appended to any existing notes.
+    #[clap(long)]
+    note: Option<String>,
+    /// Set the reading status of added and updated books, like "To Read". Needs `reading-status`
+    /// in the config file.
+    #[clap(long)]
+    reading_status: Option<String>,
+    /// Skip search results whose details can't be retrieved, instead of failing the search.
+    #[clap(long)]
+    skip_failed_results: bool,
+    /// When updating an entry, replace its existing page body with the new description.
+    /// This deletes everything on the page!
+    #[clap(long)]
+    replace_description: bool,
+    /// The format of the book, stored in the format property if one is configured. If this isn't
+    /// given, ebooks are recognized where Google Books is clear about it.
+    #[clap(long, arg_enum)]
+    format: Option<Format>,
+    /// Tag all newly created entries with this label, to find the batch of books again later.
+    #[clap(long)]
+    batch_tag: Option<String>,
+    /// Use the markdown in this file as the description, instead of the one from Google Books.
+    #[clap(long)]
+    description_file: Option<PathBuf>,
+    /// Store the Google Books description as plain text, with all markup removed, instead of
+    /// trying to preserve its formatting.
+    #[clap(long)]
+    raw_description: bool,
+    /// Add the ISBN to the titles of new entries, like "Dune [9780441013593]", so that entries
+    /// with the same title can be told apart.
+    #[clap(long)]
+    title_with_isbn: bool,
+    /// How to lay out the description on the page.
+    #[clap(long, arg_enum, default_value = "paragraph")]
+    description_style: DescriptionStyle,
+    /// Split descriptions that are a single paragraph into paragraphs of this many sentences.
+    #[clap(long)]
+    split_sentences: Option<usize>,
+    /// When updating an entry, record Google Books as the source of its metadata even if another
+    /// source was recorded before.
+    #[clap(long)]
+    update_source: bool,
+    /// Replace curly quotes, en and em dashes and ellipses in descriptions with plain ASCII
+    /// punctuation.
+    #[clap(long)]
+    plain_punctuation: bool,
+    /// How to show publication dates when choosing books and entries: as Google Books and the
+    /// database have them, only the year, or written out in full.
+    #[clap(long, arg_enum, default_value = "raw")]
+    dates: DateDisplay,
+    /// Replace double quotes in descriptions with those used in the book's language, for German
+    /// („…“) and French («…») books.
+    #[clap(long, conflicts_with = "plain-punctuation")]
+    localized_quotes: bool,
+    /// Use this as the author of books that Google Books lists without any, like anthologies, e.g.
+    /// "Various" or "Unknown".
+    #[clap(long)]
+    default_author: Option<String>,
+    /// Ignore input entered less than this many milliseconds after a prompt was shown, like a
+    /// barcode scanned while the previous book was still being added, or a scanner's extra Enter
+    /// accepting a suggestion.
+    #[clap(long, default_value = "0")]
+    debounce: u64,
+    /// Ring the terminal bell when a book was added or updated, e.g. to know when to scan the next
+    /// one.
+    #[clap(long)]
+    beep: bool,
+    /// Ask for the title to use in Notion when creating an entry, instead of always using the
+    /// title from Google Books.
+    #[clap(long)]
+    edit_title: bool,
+    /// Go through the title, authors, publisher, publish date and ISBN before writing an entry, to
+    /// keep or correct each of them.
+    #[clap(long, conflicts_with = "edit-title")]
+    edit_fields: bool,
+    /// What to do when matching entries already exist in the database, instead of asking. `update`
+    /// updates the first matching entry.
+    #[clap(long, arg_enum)]
+    on_match: Option<OnMatch>,
+    /// What to do when adding the book for one of the queries given as arguments fails: stop right
+    /// away, or go on with the other queries and list all failures at the end.
+    #[clap(long, arg_enum, default_value = "continue")]
+    on_error: OnError,
+    /// Add the Google Books volume with this ID (or from this link) instead of searching, then
+    /// exit.
+    #[clap(long, conflicts_with = "queries")]
+    volume_id: Option<String>,
+    /// The Notion database to use, as an ID or URL. Overrides the database from the config file.
+    #[clap(long)]
+    db: Option<String>,
+    /// The Notion database to use, by its name. Overrides the database from the config file.
+    #[clap(long, conflicts_with = "db")]
+    db_name: Option<String>,
+    /// Don't show progress while searching or working through many books.
+    #[clap(long)]
+    quiet: bool,
+    /// Report more of what is going on, like requests to Google Books that are retried.
+    #[clap(long)]
+    verbose: bool,
+    /// Check the configuration and API access, then exit.
+    #[clap(long)]
+    check: bool,
+    /// Print the configuration in effect, including command line overrides and defaults, then
+    /// exit. The API key and integration token are redacted.
+    #[clap(long)]
+    print_config: bool,
+    /// Find entries sharing an ISBN and offer to merge each group into one entry, archiving the
+    /// others. Exits afterwards.
+    #[clap(long)]
+    merge_duplicates: bool,
+    /// Only show what `--merge-duplicates`, `--refresh-covers`, `--backfill-isbns` or
+    /// `--mark-owned` would do, without changing anything.
+    #[clap(long)]
+    dry_run: bool,
+    /// Instead of adding books, search the database for entries to archive (by title, or by ISBN
+    /// with `--isbn`).
+    #[clap(long)]
+    archive: bool,
+    /// Mark all entries whose title contains this (or with this ISBN, with `--isbn`) as owned
+    /// after confirming, then exit. Nothing else about them is changed.
+    #[clap(long)]
+    mark_owned: Option<String>,
+    /// With `--mark-owned`, set the ownership select to this option instead of the one meaning
+    /// owned, like "Lent out".
+    #[clap(long, requires = "mark-owned")]
+    ownership_value: Option<String>,
+    /// Refresh the descriptions of all entries with an ISBN from Google Books, without changing
+    /// anything else, then exit. Existing descriptions are only replaced with
+    /// `--replace-description`.
+    #[clap(long)]
+    reprocess_descriptions: bool,
+    /// Look up the cover of every entry with an ISBN again (on Google Books, or OpenLibrary with
+    /// `--openlibrary-covers`) and update it if it changed, then exit. Covers uploaded to Notion
+    /// are left alone. With `--dry-run`, only report what would change.
+    #[clap(long)]
+    refresh_covers: bool,
+    /// With `--refresh-covers`, only look for covers for entries that don't have one yet.
+    #[clap(long, requires = "refresh-covers")]
+    missing_covers: bool,
+    /// With `--reprocess-descriptions` or `--refresh-covers`, only process entries that weren't
+    /// synced since this date (YYYY-MM-DD), or never. Needs a `last-synced` property in the config
+    /// file.
+    #[clap(long, parse(try_from_str = parse_date))]
+    since: Option<String>,
+    /// Search Google Books by title and authors for every entry without an ISBN, and store the
+    /// ISBN if exactly one edition matches confidently, then exit. Other entries are reported for
+    /// review. With `--dry-run`, only report what would be stored.
+    #[clap(long)]
+    backfill_isbns: bool,
+    /// List all authors in the database, with the number of entries for each, then exit.
+    #[clap(long)]
+    list_authors: bool,
+    /// List all publishers in the database, with the number of entries for each, then exit.
+    #[clap(long)]
+    list_publishers: bool,
+    /// List who added and who last edited the entries in the database, with the number of entries
+    /// for each, then exit. Names are only shown if the integration may read user information.
+    #[clap(long)]
+    list_contributors: bool,
+    /// Parse raw Google Books descriptions from this file and print the results, then exit. The
+    /// file contains either one description per line or a JSON array of descriptions.
+    #[clap(long)]
+    parse_test: Option<PathBuf>,
+}
+
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum OnMatch {
+    Update,
+    Create,
+    Skip,
+}
+
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum SortOrder {
+    Pages,
+    Popularity,
 }
 
-fn read_stdin_line() -> Result<String> {
-    std::io::stdout().flush().into_diagnostic()?;
-    let mut buf = String::new();
-    std::io::stdin().read_line(&mut buf).into_diagnostic()?;
-    buf.truncate(buf.trim_end().len());
-    Ok(buf)
+#[derive(clap::ArgEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum OnError {
+    Abort,
+    Continue,
 }
 
+/// Exit code for when adding the books for some queries given as arguments failed, but the others
+/// were still processed. Errors that stop the program exit with 1, and clap uses 2 for invalid
+/// arguments.
+const EXIT_SOME_FAILED: u8 = 3;
+
+/// The configuration files that are looked for, in order. The format is picked by the extension.
+const CONFIG_PATHS: [&str; 2] = ["./config.kdl", "./config.toml"];
+
 fn read_config() -> Result<Config> {
-    let path = "./config.kdl";
+    let path = CONFIG_PATHS
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+        .unwrap_or(CONFIG_PATHS[0]);
     let text = std::fs::read_to_string(path)
         .into_diagnostic()
         .wrap_err_with(|| format!("Failed to read file {}", path))?;
-    let config = knuffel::parse::<Config>(path, &text)?;
-    Ok(config)
+    parse_config(path, &text)
+}
+
+fn parse_config(path: &str, text: &str) -> Result<Config> {
+    if path.ends_with(".toml") {
+        toml::from_str(text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse {}", path))
+    } else {
+        Ok(knuffel::parse::<Config>(path, text)?)
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
-    let config = read_config().wrap_err("Failed to read configuration file")?;
-    let gbooks = GBooks::new(config.google_books_api_key);
+    if let Some(path) = &args.parse_test {
+        parse_test(path)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let notion = Notion::new(config.notion.integration_token);
-    let database = notion.database(config.notion.database_id).await?;
+    let config = read_config()
+        .wrap_err("Failed to read configuration file")?
+        .resolve(&args);
+    if args.print_config {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config.redacted()).into_diagnostic()?
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    loop {
-        let query = if args.isbn {
-            print!("Enter isbn: ");
-            format!("isbn:{}", read_stdin_line()?)
-        } else {
-            print!("Enter query: ");
-            read_stdin_line()?
-        };
+    let locale = match &config.locale {
+        Some(tag) => Locale::parse(tag)?,
+        None => Locale::default(),
+    };
+    let isbn_separator = match &config.notion.isbn_separator {
+        Some(name) => IsbnSeparator::parse(name)?,
+        None => IsbnSeparator::default(),
+    };
+    let title_template = config
+        .notion
+        .title_template
+        .as_deref()
+        .map(TitleTemplate::parse)
+        .transpose()?;
+    // `Config::resolve` has filled in the defaults of these.
+    let user_agent = config.user_agent.expect("user agent is resolved");
+    let search_concurrency = config
+        .search_concurrency
+        .expect("search concurrency is resolved");
+    let requests_per_second = config
+        .notion
+        .requests_per_second
+        .expect("requests per second is resolved");
+    let gbooks = GBooks::new(config.google_books_api_key, config.identifier_preference)?
+        .with_user_agent(user_agent.clone())
+        .with_concurrency(search_concurrency)
+        .with_verbose(args.verbose);
 
-        let search_results = gbooks
-            .search(&query)
+    let notion = Notion::new(config.notion.integration_token.clone())
+        .with_user_agent(user_agent)
+        .with_rate_limit(requests_per_second);
+    let database_id = resolve_database_id(&notion, &config.notion).await?;
+    let database = notion
+        .database(
+            database_id,
+            DatabaseOptions {
+                properties: config.notion.properties,
+                authors_database_id: config.notion.authors_database_id,
+                ownership: config.notion.ownership,
+                publisher: config.notion.publisher,
+                genres: config.notion.genres,
+                reading_status: config.notion.reading_status,
+                description_concurrency: config.notion.description_concurrency,
+                description_child_page: config.notion.description_child_page,
+                book_type: config.notion.book_type,
+                added_by: config.notion.added_by,
+                isbn_separator,
+                title_template,
+            },
+        )
+        .await?;
+
+    if args.check {
+        gbooks.check().await?;
+        println!("Google Books API key OK");
+        database.check().await?;
+        println!("Notion database OK");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.list_authors || args.list_publishers || args.list_contributors {
+        let entries = database.all_entries().await?;
+        if args.list_authors {
+            println!("Authors:");
+            print_tally(
+                locale,
+                entries.iter().flat_map(|entry| entry.authors.iter()),
+            );
+        }
+        if args.list_publishers {
+            println!("Publishers:");
+            print_tally(
+                locale,
+                entries.iter().filter_map(|entry| entry.publisher.as_ref()),
+            );
+        }
+        if args.list_contributors {
+            let names = user_names(
+                &notion,
+                entries
+                    .iter()
+                    .flat_map(|entry| entry.created_by.iter().chain(&entry.last_edited_by)),
+            )
+            .await;
+            println!("Added by:");
+            print_tally(
+                locale,
+                entries
+                    .iter()
+                    .filter_map(|entry| Some(&names[entry.created_by.as_ref()?])),
+            );
+            println!("Last edited by:");
+            print_tally(
+                locale,
+                entries
+                    .iter()
+                    .filter_map(|entry| Some(&names[entry.last_edited_by.as_ref()?])),
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut ui = TerminalUi::new(args.quiet)
+        .with_debounce(Duration::from_millis(args.debounce))
+        .with_beep(args.beep)
+        .with_locale(locale);
+
+    if args.reprocess_descriptions {
+        reprocess_descriptions(&mut ui, &gbooks, &database, &args).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.refresh_covers {
+        refresh_covers(&mut ui, &gbooks, &database, &args).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.backfill_isbns {
+        backfill_isbns(&mut ui, &gbooks, &database, args.dry_run).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.merge_duplicates {
+        merge_duplicates(&mut ui, &database, args.dry_run).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.archive {
+        archive_entries(&mut ui, &database, args.isbn).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(query) = &args.mark_owned {
+        mark_owned(&mut ui, &database, &args, query).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(volume_id) = &args.volume_id {
+        let gbook = gbooks
+            .volume(volume_id)
             .await
-            .wrap_err("Failed to search on Google Books")?
+            .wrap_err("Failed to get the volume from Google Books")?;
+        ui.report(&format!("Found {}", gbook.with_dates(args.dates, locale)));
+        add_gbook(&mut ui, &database, &args, gbook, true).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !args.queries.is_empty() {
+        let mut failed = Vec::new();
+        for query in &args.queries {
+            ui.report(&format!("Searching for {query}"));
+            if let Err(error) = add_book(&mut ui, &gbooks, &database, &args, query, false).await {
+                let error = error.wrap_err(format!("Failed to add a book for \"{query}\""));
+                if args.on_error == OnError::Abort {
+                    return Err(error);
+                }
+                ui.report(&format!("Error: {error:?}"));
+                failed.push(query.as_str());
+            }
+        }
+        return Ok(batch_summary(&mut ui, args.queries.len(), &failed));
+    }
+
+    while let Some(query) = read_query(&mut ui, args.isbn)? {
+        add_book(&mut ui, &gbooks, &database, &args, &query, true).await?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Search Google Books for `query` and add the chosen book to the database, or update an existing
+/// entry for it. If not `interactive`, the first result is used, and existing entries are handled
+/// according to `--on-match` (skipping the book if it isn't given).
+async fn add_book(
+    ui: &mut impl Ui,
+    gbooks: &GBooks,
+    database: &Database<'_>,
+    args: &Args,
+    query: &str,
+    interactive: bool,
+) -> Result<()> {
+    let locale = ui.locale();
+    let search_results = gbooks
+        .search(
+            &google_books_query(query, args),
+            args.filter,
+            |done, total| ui.progress(done, total),
+        )
+        .await
+        .wrap_err("Failed to search on Google Books")?;
+    for error in search_results.failed {
+        if !args.skip_failed_results {
+            return Err(error);
+        }
+        ui.report(&format!("Warning: Skipping result: {:?}", error));
+    }
+    if search_results.total > search_results.books.len() {
+        ui.report(&format!(
+            "Showing {} of {} results; refine your query if the book isn't listed.",
+            locale.format_number(search_results.books.len()),
+            locale.format_number(search_results.total)
+        ));
+    }
+    let search_results = search_results.books;
+    let mut search_results = filter_by_pages(search_results, args);
+    if args.isbn {
+        if let Some(prefix) = partial_isbn(query) {
+            search_results = filter_by_isbn_prefix(search_results, &prefix);
+        }
+    }
+    if search_results.is_empty() {
+        ui.report("No books found.");
+        return Ok(());
+    }
+
+    let chosen_idx = if interactive {
+        let books = search_results
+            .iter()
+            .map(|book| book.with_dates(args.dates, locale))
             .collect::<Vec<_>>();
+        match choose_book(ui, &books)? {
+            Some(idx) => idx,
+            None => return Ok(()),
+        }
+    } else {
+        0
+    };
 
-        let chosen_idx = if search_results.len() == 1 {
-            0
-        } else {
-            println!("Choose book:");
-            for (i, book) in search_results.iter().enumerate() {
-                println!("{i}: {book}");
+    if !interactive {
+        let score = matching::query_score(query, &search_results[chosen_idx]);
+        if score < args.min_match_score {
+            ui.report(&format!(
+                "Skipping {}: Low confidence match for \"{query}\" (score {score:.2}), needs review",
+                search_results[chosen_idx].with_dates(args.dates, locale)
+            ));
+            return Ok(());
+        }
+    }
+
+    let gbook = search_results.swap_remove(chosen_idx);
+    add_gbook(ui, database, args, gbook, interactive).await
+}
+
+/// Add `gbook` to the database, or update an existing entry for it. If not `interactive`, existing
+/// entries are handled according to `--on-match`.
+async fn add_gbook(
+    ui: &mut impl Ui,
+    database: &Database<'_>,
+    args: &Args,
+    mut gbook: GBook,
+    interactive: bool,
+) -> Result<()> {
+    let locale = ui.locale();
+    if args.choose_isbn && interactive {
+        if let Some(isbn) = choose_identifier(ui, &gbook.identifiers)? {
+            gbook.isbn = Some(isbn);
+        }
+    }
+    let gbook = &gbook;
+    let mut query_results = database.search(&gbook.title).await?;
+    let found = query_results.len();
+    if args.max_matches > 0 {
+        if let Some(narrowed) =
+            matching::narrow_matches(gbook, &mut query_results, args.max_matches)
+        {
+            let shown = match narrowed {
+                Narrowed::Isbn => "those with the same ISBN".to_string(),
+                Narrowed::ExactTitle => "those with exactly the same title".to_string(),
+                Narrowed::BestMatches => format!("the {} best matches", query_results.len()),
+            };
+            ui.report(&format!(
+                "Warning: {} entries match \"{}\"; only showing {shown}",
+                locale.format_number(found),
+                gbook.title
+            ));
+        }
+    }
+
+    let suggested = matching::best_match(gbook, &query_results);
+    let action = if interactive || args.on_match.is_some() {
+        let entries = query_results
+            .iter()
+            .map(|entry| entry.with_dates(args.dates, locale))
+            .collect::<Vec<_>>();
+        choose_action(ui, &entries, suggested, args.on_match)?
+    } else if query_results.is_empty() {
+        Action::CreateNew
+    } else {
+        ui.report(&format!(
+            "Skipping {}: Already in the database; use --on-match to decide what to do",
+            gbook.with_dates(args.dates, locale)
+        ));
+        Action::Skip
+    };
+
+    match action {
+        Action::Skip => {}
+        Action::CreateNew => {
+            let mut entry = create_notion_entry_from_gbook(gbook, args, database.title_template())?;
+            if args.edit_title && interactive {
+                entry.title = choose_title(ui, entry.title)?;
+            }
+            if args.edit_fields && interactive {
+                edit_fields(ui, &mut entry)?;
+            }
+            for warning in entry.enforce_limits() {
+                ui.report(&format!("Warning: {warning}"));
+            }
+            database
+                .add_entry(entry)
+                .await
+                .wrap_err("Failed to add new entry")?;
+            ui.success(&format!("Added {}", gbook.with_dates(args.dates, locale)));
+        }
+        Action::Update(entry_idx) => {
+            let mut entry_to_update = query_results[entry_idx].clone();
+            update_notion_entry_from_gbook(&mut entry_to_update, gbook, args)?;
+
+            if args.owned {
+                entry_to_update.owned = true;
             }
+            if args.edit_fields && interactive {
+                edit_fields(ui, &mut entry_to_update)?;
+            }
+            for warning in entry_to_update.enforce_limits() {
+                ui.report(&format!("Warning: {warning}"));
+            }
+
+            database
+                .update_entry(entry_to_update, args.replace_description)
+                .await
+                .wrap_err("Failed to update entry")?;
+            ui.success(&format!("Updated {}", gbook.with_dates(args.dates, locale)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Report which of the `total` queries `failed`, and return the exit code for the batch.
+fn batch_summary(ui: &mut impl Ui, total: usize, failed: &[&str]) -> ExitCode {
+    if failed.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    let locale = ui.locale();
+    ui.report(&format!(
+        "Failed to add books for {} of {} queries:",
+        locale.format_number(failed.len()),
+        locale.format_number(total)
+    ));
+    for query in failed {
+        ui.report(&format!("  {query}"));
+    }
+    ExitCode::from(EXIT_SOME_FAILED)
+}
+
+fn print_tally<'a>(locale: Locale, values: impl Iterator<Item = &'a String>) {
+    for (value, count) in tally(values) {
+        println!("{:>6}  {value}", locale.format_number(count));
+    }
+}
+
+/// Look up the names of the users with the given IDs. Users whose name can't be read are listed by
+/// their ID instead.
+async fn user_names(
+    notion: &Notion,
+    ids: impl Iterator<Item = &String>,
+) -> std::collections::HashMap<String, String> {
+    let mut names = std::collections::HashMap::new();
+    for id in ids {
+        if !names.contains_key(id) {
+            let name = notion.user_name(id).await.unwrap_or_else(|_| id.clone());
+            names.insert(id.clone(), name);
+        }
+    }
+    names
+}
+
+/// Count how often each value occurs, most frequent first.
+fn tally<'a>(values: impl Iterator<Item = &'a String>) -> Vec<(&'a str, usize)> {
+    let mut counts = std::collections::HashMap::<&str, usize>::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts
+}
 
-            print!("> ");
-            read_stdin_line()?
-                .parse::<usize>()
-                .into_diagnostic()
-                .wrap_err("Invalid result index")?
+async fn reprocess_descriptions(
+    ui: &mut impl Ui,
+    gbooks: &GBooks,
+    database: &Database<'_>,
+    args: &Args,
+) -> Result<()> {
+    let entries = entries_to_resync(database, args).await?;
+    let total = entries.len();
+    for (i, mut entry) in entries.into_iter().enumerate() {
+        let name = entry.to_string();
+        ui.item_progress(i, total, &entry.title);
+        let isbn = entry.isbn.clone().unwrap_or_default();
+
+        let results = match gbooks
+            .search(&format!("isbn:{isbn}"), None, |_, _| {})
+            .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                ui.report(&format!("Warning: Skipping {name}: {e:?}"));
+                continue;
+            }
         };
+        let gbook = match results.books.first() {
+            Some(gbook) => gbook,
+            None => {
+                ui.report(&format!("Skipping {name}: Not found on Google Books"));
+                continue;
+            }
+        };
+
+        entry.description = make_description(gbook, args)?;
+        if entry.description.is_none() {
+            ui.report(&format!("Skipping {name}: No description on Google Books"));
+            continue;
+        }
+        let id = entry.id.clone();
+        if database
+            .update_description(entry, args.replace_description)
+            .await
+            .wrap_err_with(|| format!("Failed to update description of {name}"))?
+        {
+            ui.report(&format!("Updated description of {name}"));
+        } else {
+            ui.report(&format!("Skipping {name}: Already has a description"));
+        }
+        if let Some(id) = id {
+            database.mark_synced(&id).await?;
+        }
+    }
+
+    ui.item_progress(total, total, "");
+
+    Ok(())
+}
 
-        let gbook = &search_results[chosen_idx];
-        let query_results = database.search(&gbook.title).await?;
+/// Find the ID of the database to use, by its ID or name. `--db` and `--db-name` are already applied
+/// to `config` by `Config::resolve`.
+async fn resolve_database_id(notion: &Notion, config: &NotionConfig) -> Result<String> {
+    match (&config.database_id, &config.database_name) {
+        (Some(id), _) => parse_notion_id(id).wrap_err("Invalid database ID"),
+        (None, Some(name)) => notion
+            .find_database_by_name(name)
+            .await
+            .wrap_err("Failed to find database"),
+        (None, None) => Err(miette!(
+            "No database configured; set either `database-id` or `database-name` in the config file"
+        )),
+    }
+}
 
-        enum Action {
-            CreateNew,
-            Update(usize),
+async fn refresh_covers(
+    ui: &mut impl Ui,
+    gbooks: &GBooks,
+    database: &Database<'_>,
+    args: &Args,
+) -> Result<()> {
+    let mut entries = entries_to_resync(database, args).await?;
+    if args.missing_covers {
+        // Notion can't filter by cover, so this has to happen after querying.
+        entries.retain(lacks_cover);
+    }
+    let total = entries.len();
+    let (mut updated, mut unchanged, mut not_found, mut skipped) = (0, 0, 0, 0);
+    for (i, entry) in entries.into_iter().enumerate() {
+        let name = entry.to_string();
+        ui.item_progress(i, total, &entry.title);
+        if entry.cover_uploaded {
+            ui.report(&format!("Skipping {name}: Cover was uploaded to Notion"));
+            skipped += 1;
+            continue;
         }
+        let (id, isbn) = match (&entry.id, &entry.isbn) {
+            (Some(id), Some(isbn)) => (id, isbn),
+            _ => continue,
+        };
 
-        let action = if query_results.len() > 0 {
-            println!("Choose what you want to do:");
-            println!("0: Create a new entry");
-            for (i, entry) in query_results.iter().enumerate() {
-                println!("{}: Update {entry}", i + 1);
+        let results = match gbooks
+            .search(&format!("isbn:{isbn}"), None, |_, _| {})
+            .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                ui.report(&format!("Warning: Skipping {name}: {e:?}"));
+                skipped += 1;
+                continue;
             }
-            print!("> ");
-            let choice = read_stdin_line()?
-                .parse::<usize>()
-                .into_diagnostic()
-                .wrap_err("Invalid choice")?;
-            if choice == 0 {
-                Action::CreateNew
-            } else {
-                Action::Update(choice - 1)
+        };
+        let cover_url = match results.books.first() {
+            Some(gbook) => make_cover_url(gbook, args),
+            None if args.openlibrary_covers => Some(openlibrary_cover_url(isbn)),
+            None => None,
+        };
+        let cover_url = match cover_url {
+            Some(url) => url,
+            None => {
+                ui.report(&format!("Skipping {name}: No cover found"));
+                not_found += 1;
+                continue;
             }
+        };
+        if entry.cover_url.as_ref() == Some(&cover_url) {
+            if !args.dry_run {
+                database.mark_synced(id).await?;
+            }
+            unchanged += 1;
+            continue;
+        }
+
+        if args.dry_run {
+            ui.report(&format!("Would update cover of {name} to {cover_url}"));
         } else {
-            println!("No matching entries found. Create new? (Y/N)");
-            print!("> ");
-            let choice = read_stdin_line()?;
-            match choice.as_str() {
-                "Y" | "y" | "Yes" | "yes" => Action::CreateNew,
-                _ => return Ok(()),
+            database
+                .update_cover(id, &cover_url)
+                .await
+                .wrap_err_with(|| format!("Failed to update cover of {name}"))?;
+            database.mark_synced(id).await?;
+            ui.report(&format!("Updated cover of {name}"));
+        }
+        updated += 1;
+    }
+
+    ui.item_progress(total, total, "");
+    let locale = ui.locale();
+    let updated = if args.dry_run {
+        format!("{} to update", locale.format_number(updated))
+    } else {
+        format!("{} updated", locale.format_number(updated))
+    };
+    ui.report(&format!(
+        "{updated}, {} unchanged, {} without a cover found, {} skipped.",
+        locale.format_number(unchanged),
+        locale.format_number(not_found),
+        locale.format_number(skipped)
+    ));
+
+    Ok(())
+}
+
+/// Whether `entry` has no cover at all, neither from a URL nor uploaded to Notion.
+fn lacks_cover(entry: &NotionBookEntry) -> bool {
+    entry.cover_url.is_none() && !entry.cover_uploaded
+}
+
+async fn backfill_isbns(
+    ui: &mut impl Ui,
+    gbooks: &GBooks,
+    database: &Database<'_>,
+    dry_run: bool,
+) -> Result<()> {
+    let entries = database.query(&Filter::missing_isbn()).await?;
+    let total = entries.len();
+    let (mut confident, mut ambiguous, mut not_found) = (0, 0, 0);
+    for (i, entry) in entries.into_iter().enumerate() {
+        let name = entry.to_string();
+        ui.item_progress(i, total, &entry.title);
+        let id = match &entry.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let query = title_author_query(&entry.title, &entry.authors);
+        let results = match gbooks.search(&query, None, |_, _| {}).await {
+            Ok(results) => results,
+            Err(e) => {
+                ui.report(&format!("Warning: Skipping {name}: {e:?}"));
+                continue;
             }
         };
+        match matching::isbn_match(&entry, &results.books) {
+            IsbnMatch::Confident(isbn) => {
+                confident += 1;
+                if dry_run {
+                    ui.report(&format!("Would set ISBN of {name} to {isbn}"));
+                } else {
+                    database
+                        .update_isbn(id, isbn)
+                        .await
+                        .wrap_err_with(|| format!("Failed to update ISBN of {name}"))?;
+                    ui.report(&format!("Set ISBN of {name} to {isbn}"));
+                }
+            }
+            IsbnMatch::Ambiguous(count) => {
+                ambiguous += 1;
+                ui.report(&format!(
+                    "Review {name}: {count} books with different ISBNs match"
+                ));
+            }
+            IsbnMatch::NoMatch => {
+                not_found += 1;
+                ui.report(&format!("Review {name}: No matching book found"));
+            }
+        }
+    }
+
+    ui.item_progress(total, total, "");
+    let locale = ui.locale();
+    ui.report(&format!(
+        "{} confident, {} ambiguous, {} not found.",
+        locale.format_number(confident),
+        locale.format_number(ambiguous),
+        locale.format_number(not_found)
+    ));
+
+    Ok(())
+}
+
+/// A Google Books query for the book with the given title and authors.
+fn title_author_query(title: &str, authors: &[String]) -> String {
+    // Quotes would end the phrases early, and Google Books doesn't support escaping them.
+    let phrase = |text: &str| format!("\"{}\"", text.replace('"', ""));
+    let mut query = format!("intitle:{}", phrase(title));
+    for author in authors {
+        query.push_str(&format!(" inauthor:{}", phrase(author)));
+    }
+    query
+}
+
+/// The entries to process with `--reprocess-descriptions` or `--refresh-covers`: Those with an
+/// ISBN, and with `--since`, only those that weren't synced since then.
+async fn entries_to_resync(database: &Database<'_>, args: &Args) -> Result<Vec<NotionBookEntry>> {
+    let filter = match &args.since {
+        Some(date) => database.not_synced_since(date)?,
+        None => Filter::has_isbn(),
+    };
+    database.query(&filter).await
+}
+
+/// Check that `date` looks like YYYY-MM-DD, which is what Notion expects in date filters.
+fn parse_date(date: &str) -> Result<String, String> {
+    let parts = date.split('-').collect::<Vec<_>>();
+    let valid = match parts[..] {
+        [year, month, day] => {
+            let is_number = |part: &str, len: usize| {
+                part.len() == len && part.chars().all(|c| c.is_ascii_digit())
+            };
+            is_number(year, 4)
+                && is_number(month, 2)
+                && is_number(day, 2)
+                && (1..=12).contains(&month.parse::<u32>().unwrap())
+                && (1..=31).contains(&day.parse::<u32>().unwrap())
+        }
+        _ => false,
+    };
+    if valid {
+        Ok(date.to_string())
+    } else {
+        Err(format!("expected a date like 2024-01-31, got \"{date}\""))
+    }
+}
+
+async fn merge_duplicates(ui: &mut impl Ui, database: &Database<'_>, dry_run: bool) -> Result<()> {
+    let groups = database
+        .find_duplicates()
+        .await
+        .wrap_err("Failed to search for duplicates")?;
+    if groups.is_empty() {
+        ui.report("No duplicates found.");
+        return Ok(());
+    }
+
+    for group in groups {
+        ui.report("Found duplicate entries:");
+        for entry in &group {
+            ui.report(&format!("  {entry}"));
+        }
 
-        match action {
-            Action::CreateNew => {
-                let entry = create_notion_entry_from_gbook(gbook, args.owned)?;
+        let merged = NotionBookEntry::merge(group.clone());
+        let merged_id = merged.id.clone();
+        ui.report(&format!("Merged entry: {merged}"));
+        if dry_run || !ui.confirm("Merge these entries? The others will be archived.")? {
+            continue;
+        }
+
+        database
+            .update_entry(merged, false)
+            .await
+            .wrap_err("Failed to update merged entry")?;
+        for id in group.into_iter().filter_map(|entry| entry.id) {
+            if Some(&id) != merged_id.as_ref() {
                 database
-                    .add_entry(entry)
+                    .archive_entry(&id)
                     .await
-                    .wrap_err("Failed to add new entry")?;
+                    .wrap_err_with(|| format!("Failed to archive duplicate entry {id}"))?;
             }
-            Action::Update(entry_idx) => {
-                let mut entry_to_update = query_results[entry_idx].clone();
-                update_notion_entry_from_gbook(&mut entry_to_update, gbook)?;
+        }
+    }
 
-                if args.owned {
-                    entry_to_update.owned = true;
-                }
+    Ok(())
+}
 
-                database
-                    .update_entry(entry_to_update)
-                    .await
-                    .wrap_err("Failed to update entry")?;
+async fn archive_entries(ui: &mut impl Ui, database: &Database<'_>, isbn: bool) -> Result<()> {
+    while let Some(query) = read_query(ui, isbn)? {
+        let filter = if isbn {
+            Filter::isbn_equals(&query)
+        } else {
+            Filter::title_contains(&query)
+        };
+        let entries = database.query(&filter).await?;
+        if entries.is_empty() {
+            ui.report("No matching entries found.");
+            continue;
+        }
+
+        let entry = match choose_book(ui, &entries)? {
+            Some(idx) => &entries[idx],
+            None => continue,
+        };
+        if ui.confirm(&format!("Archive {entry}?"))? {
+            let id = entry
+                .id
+                .as_deref()
+                .ok_or_else(|| miette!("Entry to archive has no ID"))?;
+            database
+                .archive_entry(id)
+                .await
+                .wrap_err("Failed to archive entry")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mark all entries whose title contains `query` (or with ISBN `query`, with `--isbn`) as owned, or
+/// set their ownership to `--ownership-value`. Entries that are already owned are left alone.
+async fn mark_owned(
+    ui: &mut impl Ui,
+    database: &Database<'_>,
+    args: &Args,
+    query: &str,
+) -> Result<()> {
+    let value = args.ownership_value.as_deref();
+    database.check_ownership_value(value)?;
+    let mut filter = if args.isbn {
+        Filter::isbn_equals(query)
+    } else {
+        Filter::title_contains(query)
+    };
+    if value.is_none() {
+        filter = Filter::And(vec![filter, Filter::not_owned(database.ownership())]);
+    }
+    let entries = database.query(&filter).await?;
+    if entries.is_empty() {
+        ui.report("No matching entries to change.");
+        return Ok(());
+    }
+
+    let target = value.unwrap_or("owned");
+    let count = ui.locale().format_number(entries.len());
+    for entry in &entries {
+        ui.report(&format!("  {entry}"));
+    }
+    if args.dry_run {
+        ui.report(&format!("Would mark {count} entries as {target}."));
+        return Ok(());
+    }
+    if !ui.confirm(&format!("Mark {count} entries as {target}?"))? {
+        return Ok(());
+    }
+
+    let total = entries.len();
+    for (i, entry) in entries.iter().enumerate() {
+        ui.item_progress(i, total, &entry.title);
+        let id = entry
+            .id
+            .as_deref()
+            .ok_or_else(|| miette!("Entry to mark as {} has no ID", target))?;
+        database
+            .set_ownership(id, value)
+            .await
+            .wrap_err_with(|| format!("Failed to mark {entry} as {target}"))?;
+    }
+    ui.item_progress(total, total, "");
+    ui.report(&format!("Marked {count} entries as {target}."));
+
+    Ok(())
+}
+
+/// Ask for the next search query, an ISBN if `isbn` is set. Returns `None` if the user wants to
+/// quit, by entering nothing, `:q` or `quit`, or by ending the input.
+fn read_query(ui: &mut impl Ui, isbn: bool) -> Result<Option<String>> {
+    let message = if isbn {
+        "Enter isbn: "
+    } else {
+        "Enter query: "
+    };
+    let input = match ui.prompt(message) {
+        Ok(input) => input,
+        Err(e) if e.downcast_ref::<EndOfInput>().is_some() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let input = input.trim();
+    if matches!(input, "" | ":q" | "quit") {
+        return Ok(None);
+    }
+
+    Ok(Some(input.to_string()))
+}
+
+/// Turn the user's query into a query for Google Books.
+fn google_books_query(query: &str, args: &Args) -> String {
+    if args.isbn {
+        // The `isbn:` operator only finds complete ISBNs, so search for the digits of partial ones
+        // and pick out the books that match afterwards.
+        match partial_isbn(query) {
+            Some(digits) => digits,
+            None => format!("isbn:{query}"),
+        }
+    } else if args.title_search {
+        // Quotes would end the phrase early, and Google Books doesn't support escaping them.
+        format!("intitle:\"{}\"", query.replace('"', ""))
+    } else {
+        query.to_string()
+    }
+}
+
+/// Partial ISBNs shorter than this match too many books to be worth searching for.
+const MIN_PARTIAL_ISBN_DIGITS: usize = 4;
+
+/// If `query` looks like the beginning of an ISBN, but not a whole one, return its digits. ISBN-13s
+/// start with 978 or 979, so longer prefixes have to as well.
+fn partial_isbn(query: &str) -> Option<String> {
+    let digits = query.replace(|c: char| c == '-' || c.is_whitespace(), "");
+    let plausible = digits.chars().all(|c| c.is_ascii_digit())
+        && match digits.len() {
+            len if len < MIN_PARTIAL_ISBN_DIGITS => false,
+            len if len < 10 => true,
+            11 | 12 => digits.starts_with("978") || digits.starts_with("979"),
+            _ => false,
+        };
+    plausible.then_some(digits)
+}
+
+/// Keep only the books with an identifier starting with the partial ISBN `prefix`.
+fn filter_by_isbn_prefix(mut search_results: Vec<GBook>, prefix: &str) -> Vec<GBook> {
+    search_results.retain(|book| {
+        book.identifiers
+            .iter()
+            .any(|(_, identifier)| identifier.replace('-', "").starts_with(prefix))
+    });
+    search_results
+}
+
+fn filter_by_pages(mut search_results: Vec<GBook>, args: &Args) -> Vec<GBook> {
+    // Books with an unknown page count are kept when filtering, since we can't tell whether they
+    // would match.
+    search_results.retain(|book| match book.page_count {
+        Some(pages) => {
+            args.min_pages.is_none_or(|min| pages >= min)
+                && args.max_pages.is_none_or(|max| pages <= max)
+        }
+        None => true,
+    });
+
+    // Stable sorts, so books with the same (or no) page count or ratings keep Google's ordering.
+    let sort = if args.sort_by_pages {
+        Some(SortOrder::Pages)
+    } else {
+        args.sort
+    };
+    match sort {
+        Some(SortOrder::Pages) => {
+            search_results.sort_by_key(|book| std::cmp::Reverse(book.page_count))
+        }
+        Some(SortOrder::Popularity) => search_results.sort_by(|a, b| {
+            // `None` is less than any rating, so comparing in reverse puts unrated books last.
+            b.ratings_count.cmp(&a.ratings_count).then_with(|| {
+                b.average_rating
+                    .partial_cmp(&a.average_rating)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+        }),
+        None => {}
+    }
+
+    search_results
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    CreateNew,
+    Update(usize),
+    Skip,
+}
+
+/// Let the user choose one of the search results. Returns `None` if they'd rather search again
+/// with a different query, by entering `r`.
+fn choose_book(ui: &mut impl Ui, search_results: &[impl Display]) -> Result<Option<usize>> {
+    if search_results.len() == 1 {
+        return Ok(Some(0));
+    }
+
+    ui.report("Choose book (or r to search again):");
+    for (i, book) in search_results.iter().enumerate() {
+        ui.report(&format!("{i}: {book}"));
+    }
+
+    let choice = ui.prompt("> ")?;
+    if choice.trim() == "r" {
+        return Ok(None);
+    }
+    let choice = choice
+        .trim()
+        .parse::<usize>()
+        .into_diagnostic()
+        .wrap_err("Invalid result index")?;
+    if choice >= search_results.len() {
+        return Err(miette!("Invalid result index {}", choice));
+    }
+
+    Ok(Some(choice))
+}
+
+/// Let the user choose which of a book's `identifiers` to store. Returns `None` if there is no
+/// choice to make.
+fn choose_identifier(ui: &mut impl Ui, identifiers: &[(String, String)]) -> Result<Option<String>> {
+    if identifiers.len() < 2 {
+        return Ok(None);
+    }
+
+    let options = identifiers
+        .iter()
+        .map(|(ty, identifier)| format!("{identifier} ({ty})"))
+        .collect::<Vec<_>>();
+    let choice = ui
+        .select("Choose the identifier to store:", &options)
+        .wrap_err("Invalid identifier index")?;
+    Ok(Some(identifiers[choice].1.clone()))
+}
+
+/// Let the user change `title`, keeping it as is if they don't enter anything.
+fn choose_title(ui: &mut impl Ui, title: String) -> Result<String> {
+    let input = ui.prompt(&format!("Title in Notion [{title}]: "))?;
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(title)
+    } else {
+        Ok(input.to_string())
+    }
+}
+
+/// Go through the main fields of `entry`, letting the user keep or replace each of them.
+fn edit_fields(ui: &mut impl Ui, entry: &mut NotionBookEntry) -> Result<()> {
+    ui.report("Enter nothing to keep a value, a new one to replace it, or - to clear it.");
+    entry.title = choose_title(ui, std::mem::take(&mut entry.title))?;
+    edit_authors(ui, &mut entry.authors, &mut entry.author_ids)?;
+    let publisher = edit_field(ui, "Publisher", entry.publisher.clone())?;
+    if publisher != entry.publisher {
+        entry.publisher = publisher;
+        entry.publisher_id = None;
+    }
+    entry.published_date = edit_field(ui, "Publish date", entry.published_date.take())?;
+    entry.isbn = edit_field(ui, "ISBN", entry.isbn.take())?;
+    Ok(())
+}
+
+/// Let the user change an optional `value`, keeping it if they don't enter anything and clearing
+/// it if they enter "-".
+fn edit_field(ui: &mut impl Ui, name: &str, value: Option<String>) -> Result<Option<String>> {
+    let input = ui.prompt(&format!("{name} [{}]: ", value.as_deref().unwrap_or("")))?;
+    match input.trim() {
+        "" => Ok(value),
+        "-" => Ok(None),
+        input => Ok(Some(input.to_string())),
+    }
+}
+
+/// Let the user add and remove authors until they are done. `author_ids` are kept in step with
+/// `authors`; new authors don't have an ID yet.
+fn edit_authors(
+    ui: &mut impl Ui,
+    authors: &mut Vec<String>,
+    author_ids: &mut Vec<Option<String>>,
+) -> Result<()> {
+    loop {
+        ui.report("Authors:");
+        for (i, author) in authors.iter().enumerate() {
+            ui.report(&format!("{i}: {author}"));
+        }
+        let input = ui.prompt("+Name to add an author, -N to remove one, nothing to go on: ")?;
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(());
+        }
+        if let Some(name) = input.strip_prefix('+') {
+            let name = name.trim();
+            if !name.is_empty() {
+                authors.push(name.to_string());
+                author_ids.push(None);
+            }
+        } else if let Some(index) = input.strip_prefix('-') {
+            match index.trim().parse::<usize>() {
+                Ok(i) if i < authors.len() => {
+                    authors.remove(i);
+                    if i < author_ids.len() {
+                        author_ids.remove(i);
+                    }
+                }
+                _ => ui.report(&format!("There is no author {}", index.trim())),
             }
+        } else {
+            ui.report("Enter +Name or -N");
         }
     }
 }
 
-fn make_description(gbook: &GBook) -> Result<Option<RichText>> {
-    if let Some(text) = &gbook.description {
-        Ok(Some(
-            descriptions::parse_text(text).wrap_err("Failed to parse description!")?,
-        ))
+/// Decide whether to create a new entry or update one of the existing `query_results`. Declining
+/// to do either skips the book. `suggested` is the index of the result that seems to be the same
+/// book, if any; it is offered as the default, and updated with `--on-match update`. With an
+/// `on_match` policy nobody is asked, so a book without matches is just created.
+fn choose_action(
+    ui: &mut impl Ui,
+    query_results: &[impl Display],
+    suggested: Option<usize>,
+    on_match: Option<OnMatch>,
+) -> Result<Action> {
+    if !query_results.is_empty() {
+        if let Some(on_match) = on_match {
+            return Ok(match on_match {
+                OnMatch::Update => Action::Update(suggested.unwrap_or(0)),
+                OnMatch::Create => Action::CreateNew,
+                OnMatch::Skip => Action::Skip,
+            });
+        }
+
+        let options = std::iter::once("Create a new entry".to_string())
+            .chain(query_results.iter().map(|entry| format!("Update {entry}")))
+            .collect::<Vec<_>>();
+        let choice = ui.select_with_default(
+            "Choose what you want to do:",
+            &options,
+            suggested.map(|i| i + 1),
+        )?;
+        if choice == 0 {
+            Ok(Action::CreateNew)
+        } else {
+            Ok(Action::Update(choice - 1))
+        }
+    } else if on_match.is_some() || ui.confirm("No matching entries found. Create new?")? {
+        Ok(Action::CreateNew)
     } else {
-        Ok(None)
+        Ok(Action::Skip)
     }
 }
 
-fn create_notion_entry_from_gbook(gbook: &GBook, owned: bool) -> Result<NotionBookEntry> {
-    let description = make_description(gbook)?;
+fn parse_test(path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read file {}", path.display()))?;
+    let inputs: Vec<String> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(&text)
+            .into_diagnostic()
+            .wrap_err("Failed to parse JSON array of descriptions")?
+    } else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+
+    for (i, input) in inputs.iter().enumerate() {
+        println!("=== {i}: {input}");
+        match descriptions::parse_text(input) {
+            Ok(parsed) => println!("{parsed}"),
+            Err(e) => println!("Error: {e:?}"),
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn make_description(gbook: &GBook, args: &Args) -> Result<Option<Vec<Block>>> {
+    let blocks = if let Some(path) = &args.description_file {
+        let text = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read file {}", path.display()))?;
+        markdown::parse_markdown(&text)
+    } else if let Some(text) = &gbook.description {
+        let text = if args.raw_description {
+            let text = descriptions::strip_tags(text);
+            RichText {
+                fragments: vec![TextFragment::new(text, TextStyle::unstyled())],
+            }
+        } else {
+            descriptions::parse_text(text).wrap_err("Failed to parse description!")?
+        };
+        vec![Block::Paragraph(text)]
+    } else {
+        return Ok(None);
+    };
+
+    let blocks = if args.plain_punctuation {
+        descriptions::normalize_punctuation(blocks)
+    } else {
+        blocks
+    };
+    let blocks = match (&gbook.language, args.localized_quotes) {
+        (Some(language), true) => descriptions::localize_quotes(blocks, language),
+        _ => blocks,
+    };
+    let blocks = match args.split_sentences {
+        Some(sentences) => descriptions::split_sentences(blocks, sentences),
+        None => blocks,
+    };
+    Ok(Some(args.description_style.apply(blocks)))
+}
+
+fn make_cover_url(gbook: &GBook, args: &Args) -> Option<String> {
+    // OpenLibrary doesn't have a cover for every ISBN (and will just serve a blank image in that
+    // case), so only fall back to it if explicitly asked to.
+    gbook.image_link.clone().or_else(|| {
+        if args.openlibrary_covers {
+            gbook.isbn.as_deref().map(openlibrary_cover_url)
+        } else {
+            None
+        }
+    })
+}
+
+fn openlibrary_cover_url(isbn: &str) -> String {
+    format!("https://covers.openlibrary.org/b/isbn/{isbn}-L.jpg")
+}
+
+fn create_notion_entry_from_gbook(
+    gbook: &GBook,
+    args: &Args,
+    title_template: Option<&TitleTemplate>,
+) -> Result<NotionBookEntry> {
+    let description = make_description(gbook, args)?;
+    let authors = authors_or_default(gbook, args);
+    let author_ids = vec![None; authors.len()];
+    let title = match title_template {
+        Some(template) => template.render(gbook),
+        None => gbook.title.clone(),
+    };
 
     Ok(NotionBookEntry {
         id: None,
-        owned,
-        title: gbook.title.clone(),
-        authors: gbook.authors.clone(),
-        author_ids: vec![None; gbook.authors.len()],
+        owned: args.owned,
+        title: match (&gbook.isbn, args.title_with_isbn) {
+            (Some(isbn), true) => title_with_isbn(&title, isbn),
+            _ => title,
+        },
+        authors,
+        author_ids,
         publisher: gbook.publisher.clone(),
         publisher_id: None,
+        notes: args.note.clone(),
+        edition: gbook.edition.clone(),
+        original_year: gbook.original_year,
+        format: args
+            .format
+            .or(gbook.format)
+            .map(|format| format.to_string()),
+        dimensions: gbook.dimensions.clone(),
+        series_position: gbook.series_position,
+        categories: gbook.categories.clone(),
+        source: Some(gbooks::SOURCE.to_string()),
+        reading_status: args.reading_status.clone(),
+        epub_available: gbook.epub_available,
+        pdf_available: gbook.pdf_available,
+        last_synced: Some(notion::today()),
+        volume_id: gbook.volume_id.clone(),
+        self_link: gbook.self_link.clone(),
+        batch_tag: args.batch_tag.clone(),
+        raw_description: gbook.description.clone(),
+        created_by: None,
+        last_edited_by: None,
         published_date: gbook.published_date.clone(),
         isbn: gbook.isbn.clone(),
-        cover_url: gbook.image_link.clone(),
+        isbns: gbook.isbns(),
+        cover_url: make_cover_url(gbook, args),
+        cover_uploaded: false,
         description,
         had_original_description: false,
     })
 }
 
+/// The authors of `gbook` without duplicates, or the `--default-author` if it doesn't list any.
+fn authors_or_default(gbook: &GBook, args: &Args) -> Vec<String> {
+    match &args.default_author {
+        Some(author) if gbook.authors.is_empty() => vec![author.clone()],
+        _ => matching::dedupe_authors(&gbook.authors),
+    }
+}
+
+/// Add the ISBN to the end of a title, to tell apart entries with the same title.
+fn title_with_isbn(title: &str, isbn: &str) -> String {
+    format!("{title} [{isbn}]")
+}
+
 fn update_notion_entry_from_gbook(
     entry_to_update: &mut NotionBookEntry,
     gbook: &GBook,
+    args: &Args,
 ) -> Result<()> {
     if entry_to_update.authors.is_empty() {
-        entry_to_update.authors = gbook.authors.clone();
+        entry_to_update.authors = authors_or_default(gbook, args);
         entry_to_update.author_ids = vec![None; entry_to_update.authors.len()];
     }
 
@@ -205,18 +1645,701 @@ fn update_notion_entry_from_gbook(
         entry_to_update.published_date = gbook.published_date.clone();
     }
 
+    if entry_to_update.edition.is_none() {
+        entry_to_update.edition = gbook.edition.clone();
+    }
+    if entry_to_update.original_year.is_none() {
+        entry_to_update.original_year = gbook.original_year;
+    }
+
+    // An explicitly given format overrides the existing one, but a guessed one doesn't.
+    if let Some(format) = args.format {
+        entry_to_update.format = Some(format.to_string());
+    } else if entry_to_update.format.is_none() {
+        entry_to_update.format = gbook.format.map(|format| format.to_string());
+    }
+
+    if gbook.description.is_some() {
+        entry_to_update.raw_description = gbook.description.clone();
+    }
+
+    if entry_to_update.dimensions.is_none() {
+        entry_to_update.dimensions = gbook.dimensions.clone();
+    }
+    if entry_to_update.series_position.is_none() {
+        entry_to_update.series_position = gbook.series_position;
+    }
+    if entry_to_update.categories.is_empty() {
+        entry_to_update.categories = gbook.categories.clone();
+    }
+
     if entry_to_update.isbn.is_none() {
         entry_to_update.isbn = gbook.isbn.clone();
     }
+    if entry_to_update.isbns.is_empty() {
+        entry_to_update.isbns = gbook.isbns();
+    }
+    if entry_to_update.volume_id.is_none() {
+        entry_to_update.volume_id = gbook.volume_id.clone();
+    }
+    if entry_to_update.self_link.is_none() {
+        entry_to_update.self_link = gbook.self_link.clone();
+    }
 
-    if entry_to_update.cover_url.is_none() {
-        entry_to_update.cover_url = gbook.image_link.clone();
+    // Availability changes over time, so the current one is always stored.
+    if gbook.epub_available.is_some() {
+        entry_to_update.epub_available = gbook.epub_available;
+    }
+    if gbook.pdf_available.is_some() {
+        entry_to_update.pdf_available = gbook.pdf_available;
     }
 
-    if !entry_to_update.had_original_description {
-        let descr = make_description(gbook)?;
+    if args.reading_status.is_some() {
+        entry_to_update.reading_status = args.reading_status.clone();
+    }
+
+    if entry_to_update.source.is_none() || args.update_source {
+        entry_to_update.source = Some(gbooks::SOURCE.to_string());
+    }
+
+    entry_to_update.last_synced = Some(notion::today());
+
+    if entry_to_update.cover_url.is_none() && !entry_to_update.cover_uploaded {
+        entry_to_update.cover_url = make_cover_url(gbook, args);
+    }
+
+    if let Some(note) = &args.note {
+        entry_to_update.notes = Some(match entry_to_update.notes.take() {
+            Some(existing) => format!("{existing}\n{note}"),
+            None => note.clone(),
+        });
+    }
+
+    if !entry_to_update.had_original_description || args.replace_description {
+        let descr = make_description(gbook, args)?;
         entry_to_update.description = descr;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::ScriptedUi;
+
+    #[test]
+    fn toml_and_kdl_configs_are_the_same() {
+        let kdl = r#"
+            google-books-api-key "key"
+            identifier-preference "ISBN_13" "ISBN_10"
+            locale "de-DE"
+            notion {
+                integration-token "token"
+                database-id "abc"
+                ownership {
+                    property "Owned"
+                    checkbox
+                }
+                properties {
+                    edition "Edition"
+                    added-by "Added By"
+                }
+                publisher {
+                    collapse-whitespace
+                    alias "Penguin Books Ltd" "Penguin"
+                }
+                genres {
+                    contains "Fantasy" "Fantasy"
+                    drop-unmapped
+                }
+                requests-per-second 2
+                description-child-page
+            }
+        "#;
+        let toml = r#"
+            google-books-api-key = "key"
+            identifier-preference = ["ISBN_13", "ISBN_10"]
+            locale = "de-DE"
+
+            [notion]
+            integration-token = "token"
+            database-id = "abc"
+            requests-per-second = 2
+            description-child-page = true
+
+            [notion.ownership]
+            property = "Owned"
+            checkbox = true
+
+            [notion.properties]
+            edition = "Edition"
+            added-by = "Added By"
+
+            [notion.publisher]
+            collapse-whitespace = true
+            alias = [{ name = "Penguin Books Ltd", replacement = "Penguin" }]
+
+            [notion.genres]
+            contains = [{ category = "Fantasy", genre = "Fantasy" }]
+            drop-unmapped = true
+        "#;
+        let from_kdl = parse_config("config.kdl", kdl).unwrap();
+        let from_toml = parse_config("config.toml", toml).unwrap();
+        assert_eq!(from_kdl, from_toml);
+        assert!(from_toml.notion.ownership.is_checkbox());
+    }
+
+    #[test]
+    fn resolved_config_has_overrides_and_defaults() {
+        let config = parse_config(
+            "config.toml",
+            "google-books-api-key = \"key\"\n[notion]\nintegration-token = \"token\"\ndatabase-id = \"abc\"",
+        )
+        .unwrap();
+        let args = Args::parse_from(["notion-books", "--db-name", "Books"]);
+        let config = config.resolve(&args).redacted();
+        assert_eq!(config.notion.database_id, None);
+        assert_eq!(config.notion.database_name.as_deref(), Some("Books"));
+        assert_eq!(config.user_agent.as_deref(), Some(DEFAULT_USER_AGENT));
+        assert_eq!(config.search_concurrency, Some(DEFAULT_SEARCH_CONCURRENCY));
+
+        let printed = serde_json::to_string(&config).unwrap();
+        assert!(!printed.contains("\"key\""));
+        assert!(!printed.contains("\"token\""));
+        assert!(printed.contains(REDACTED));
+    }
+
+    #[test]
+    fn toml_config_defaults_are_the_same() {
+        let from_kdl = parse_config(
+            "config.kdl",
+            "google-books-api-key \"key\"\nnotion { integration-token \"token\"; }",
+        )
+        .unwrap();
+        let from_toml = parse_config(
+            "config.toml",
+            "google-books-api-key = \"key\"\n[notion]\nintegration-token = \"token\"",
+        )
+        .unwrap();
+        assert_eq!(from_kdl, from_toml);
+        assert_eq!(from_toml.identifier_preference, vec!["ISBN_13"]);
+    }
+
+    fn book_with_pages(title: &str, page_count: Option<u32>) -> GBook {
+        GBook {
+            page_count,
+            ..GBook::new(title)
+        }
+    }
+
+    fn titles(books: &[GBook]) -> Vec<&str> {
+        books.iter().map(|book| book.title.as_str()).collect()
+    }
+
+    #[test]
+    fn filter_by_page_range() {
+        let args = Args::parse_from(["notion-books", "--min-pages", "300", "--max-pages", "700"]);
+        let books = vec![
+            book_with_pages("Abridged", Some(250)),
+            book_with_pages("Unabridged", Some(600)),
+            book_with_pages("Annotated", Some(900)),
+            book_with_pages("Unknown", None),
+        ];
+        assert_eq!(
+            titles(&filter_by_pages(books, &args)),
+            ["Unabridged", "Unknown"]
+        );
+    }
+
+    #[test]
+    fn sort_by_page_count() {
+        let args = Args::parse_from(["notion-books", "--sort-by-pages"]);
+        let books = vec![
+            book_with_pages("Unknown", None),
+            book_with_pages("Abridged", Some(250)),
+            book_with_pages("Unabridged", Some(600)),
+        ];
+        assert_eq!(
+            titles(&filter_by_pages(books, &args)),
+            ["Unabridged", "Abridged", "Unknown"]
+        );
+    }
+
+    #[test]
+    fn sort_by_popularity() {
+        let args = Args::parse_from(["notion-books", "--sort", "popularity"]);
+        let rated = |title, ratings_count, average_rating| GBook {
+            ratings_count,
+            average_rating,
+            ..book_with_pages(title, None)
+        };
+        let books = vec![
+            rated("Unrated", None, None),
+            rated("Reprint", Some(12), Some(4.0)),
+            rated("Well liked", Some(12), Some(4.5)),
+            rated("Main edition", Some(3400), Some(4.2)),
+        ];
+        assert_eq!(
+            titles(&filter_by_pages(books, &args)),
+            ["Main edition", "Well liked", "Reprint", "Unrated"]
+        );
+    }
+
+    #[test]
+    fn single_book_is_chosen_without_asking() {
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(choose_book(&mut ui, &["Dune"]).unwrap(), Some(0));
+        assert!(ui.output.is_empty());
+    }
+
+    #[test]
+    fn book_is_chosen_by_index() {
+        let mut ui = ScriptedUi::new(&["1"]);
+        assert_eq!(
+            choose_book(&mut ui, &["Dune", "Dune Messiah"]).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            ui.output,
+            [
+                "Choose book (or r to search again):",
+                "0: Dune",
+                "1: Dune Messiah",
+                "> "
+            ]
+        );
+    }
+
+    #[test]
+    fn search_can_be_repeated_instead_of_choosing() {
+        let mut ui = ScriptedUi::new(&["r"]);
+        assert_eq!(
+            choose_book(&mut ui, &["Dune", "Dune Messiah"]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn out_of_range_book_index_is_rejected() {
+        let mut ui = ScriptedUi::new(&["2"]);
+        assert!(choose_book(&mut ui, &["Dune", "Dune Messiah"]).is_err());
+    }
+
+    #[test]
+    fn identifier_is_chosen() {
+        let identifiers = [
+            ("ISBN_13".to_string(), "9780441013593".to_string()),
+            ("ISBN_10".to_string(), "0441013597".to_string()),
+        ];
+        let mut ui = ScriptedUi::new(&["1"]);
+        assert_eq!(
+            choose_identifier(&mut ui, &identifiers).unwrap().as_deref(),
+            Some("0441013597")
+        );
+        assert_eq!(
+            ui.output,
+            [
+                "Choose the identifier to store:",
+                "0: 9780441013593 (ISBN_13)",
+                "1: 0441013597 (ISBN_10)",
+                "> "
+            ]
+        );
+
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(choose_identifier(&mut ui, &identifiers[..1]).unwrap(), None);
+    }
+
+    #[test]
+    fn title_is_overridden() {
+        let mut ui = ScriptedUi::new(&["Dune"]);
+        assert_eq!(
+            choose_title(&mut ui, "Dune (Dune Chronicles, Book 1)".to_string()).unwrap(),
+            "Dune"
+        );
+        assert_eq!(
+            ui.output,
+            ["Title in Notion [Dune (Dune Chronicles, Book 1)]: "]
+        );
+    }
+
+    #[test]
+    fn empty_title_input_keeps_title() {
+        let mut ui = ScriptedUi::new(&[""]);
+        assert_eq!(choose_title(&mut ui, "Dune".to_string()).unwrap(), "Dune");
+    }
+
+    #[test]
+    fn fields_are_kept_without_input() {
+        let mut entry = NotionBookEntry {
+            authors: vec!["Frank Herbert".to_string()],
+            author_ids: vec![Some("herbert".to_string())],
+            publisher: Some("Ace".to_string()),
+            publisher_id: Some("ace".to_string()),
+            published_date: Some("1990".to_string()),
+            isbn: Some("9780441013593".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        let expected = entry.clone();
+        let mut ui = ScriptedUi::new(&["", "", "", "", ""]);
+        edit_fields(&mut ui, &mut entry).unwrap();
+        assert_eq!(entry, expected);
+        assert_eq!(
+            ui.output,
+            [
+                "Enter nothing to keep a value, a new one to replace it, or - to clear it.",
+                "Title in Notion [Dune]: ",
+                "Authors:",
+                "0: Frank Herbert",
+                "+Name to add an author, -N to remove one, nothing to go on: ",
+                "Publisher [Ace]: ",
+                "Publish date [1990]: ",
+                "ISBN [9780441013593]: ",
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_are_replaced_and_cleared() {
+        let mut entry = NotionBookEntry {
+            authors: vec!["Frank Herbert".to_string(), "Herbert".to_string()],
+            author_ids: vec![Some("herbert".to_string()), None],
+            publisher: Some("Ace".to_string()),
+            publisher_id: Some("ace".to_string()),
+            isbn: Some("9780441013593".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        let mut ui = ScriptedUi::new(&[
+            "Dune Messiah",
+            "-1",
+            "+ Brian Herbert ",
+            "-5",
+            "",
+            "Ace Books",
+            "1969",
+            "-",
+        ]);
+        edit_fields(&mut ui, &mut entry).unwrap();
+        assert_eq!(entry.title, "Dune Messiah");
+        assert_eq!(entry.authors, ["Frank Herbert", "Brian Herbert"]);
+        assert_eq!(entry.author_ids, [Some("herbert".to_string()), None]);
+        assert_eq!(entry.publisher.as_deref(), Some("Ace Books"));
+        assert_eq!(entry.publisher_id, None);
+        assert_eq!(entry.published_date.as_deref(), Some("1969"));
+        assert_eq!(entry.isbn, None);
+        assert!(ui.output.contains(&"There is no author 5".to_string()));
+    }
+
+    #[test]
+    fn batch_summary_lists_failures() {
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(batch_summary(&mut ui, 3, &[]), ExitCode::SUCCESS);
+        assert!(ui.output.is_empty());
+
+        assert_eq!(
+            batch_summary(&mut ui, 3, &["Dune", "9780441013593"]),
+            ExitCode::from(EXIT_SOME_FAILED)
+        );
+        assert_eq!(
+            ui.output,
+            [
+                "Failed to add books for 2 of 3 queries:",
+                "  Dune",
+                "  9780441013593"
+            ]
+        );
+    }
+
+    #[test]
+    fn values_are_tallied_by_frequency() {
+        let values = ["Ace", "Tor", "Ace", "Gollancz", "Tor", "Ace"].map(String::from);
+        assert_eq!(
+            tally(values.iter()),
+            [("Ace", 3), ("Tor", 2), ("Gollancz", 1)]
+        );
+    }
+
+    #[test]
+    fn google_books_query_modes() {
+        let args = Args::parse_from(["notion-books"]);
+        assert_eq!(google_books_query("dune herbert", &args), "dune herbert");
+
+        let args = Args::parse_from(["notion-books", "--isbn"]);
+        assert_eq!(
+            google_books_query("9780441013593", &args),
+            "isbn:9780441013593"
+        );
+
+        assert_eq!(google_books_query("978-0441", &args), "9780441");
+
+        let args = Args::parse_from(["notion-books", "--title-search"]);
+        assert_eq!(
+            google_books_query("the \"best\" book", &args),
+            "intitle:\"the best book\""
+        );
+    }
+
+    #[test]
+    fn title_author_queries() {
+        assert_eq!(title_author_query("Dune", &[]), "intitle:\"Dune\"");
+        assert_eq!(
+            title_author_query(
+                "The \"Good\" Omens",
+                &["Terry Pratchett".to_string(), "Neil Gaiman".to_string()]
+            ),
+            "intitle:\"The Good Omens\" inauthor:\"Terry Pratchett\" inauthor:\"Neil Gaiman\""
+        );
+    }
+
+    #[test]
+    fn default_author_fills_in_missing_authors() {
+        let anthology = book_with_pages("The Best of Science Fiction", None);
+        let args = Args::parse_from(["notion-books", "--default-author", "Various"]);
+        let entry = create_notion_entry_from_gbook(&anthology, &args, None).unwrap();
+        assert_eq!(entry.authors, ["Various"]);
+        assert_eq!(entry.author_ids.len(), 1);
+
+        let mut dune = book_with_pages("Dune", None);
+        dune.authors = vec!["Frank Herbert".to_string()];
+        let entry = create_notion_entry_from_gbook(&dune, &args, None).unwrap();
+        assert_eq!(entry.authors, ["Frank Herbert"]);
+
+        let args = Args::parse_from(["notion-books"]);
+        let entry = create_notion_entry_from_gbook(&anthology, &args, None).unwrap();
+        assert!(entry.authors.is_empty());
+    }
+
+    #[test]
+    fn duplicate_authors_are_not_added() {
+        let mut hobbit = book_with_pages("The Hobbit", None);
+        hobbit.authors = vec![
+            "Tolkien".to_string(),
+            "J. R. R. Tolkien".to_string(),
+            "J.R.R. Tolkien".to_string(),
+        ];
+        let args = Args::parse_from(["notion-books"]);
+        let entry = create_notion_entry_from_gbook(&hobbit, &args, None).unwrap();
+        assert_eq!(entry.authors, ["J. R. R. Tolkien"]);
+        assert_eq!(entry.author_ids.len(), 1);
+    }
+
+    #[test]
+    fn missing_covers_are_recognized() {
+        assert!(lacks_cover(&NotionBookEntry::new("Dune")));
+
+        let linked = NotionBookEntry {
+            cover_url: Some("https://example.com/cover.jpg".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        assert!(!lacks_cover(&linked));
+
+        let uploaded = NotionBookEntry {
+            cover_uploaded: true,
+            ..NotionBookEntry::new("Dune")
+        };
+        assert!(!lacks_cover(&uploaded));
+
+        assert!(Args::try_parse_from(["notion-books", "--missing-covers"]).is_err());
+        assert!(
+            Args::try_parse_from(["notion-books", "--refresh-covers", "--missing-covers"]).is_ok()
+        );
+    }
+
+    #[test]
+    fn isbn_is_added_to_title() {
+        let mut gbook = book_with_pages("Dune", None);
+        gbook.isbn = Some("9780441013593".to_string());
+
+        let args = Args::parse_from(["notion-books", "--title-with-isbn"]);
+        let entry = create_notion_entry_from_gbook(&gbook, &args, None).unwrap();
+        assert_eq!(entry.title, "Dune [9780441013593]");
+
+        gbook.isbn = None;
+        let entry = create_notion_entry_from_gbook(&gbook, &args, None).unwrap();
+        assert_eq!(entry.title, "Dune");
+    }
+
+    #[test]
+    fn since_takes_a_date() {
+        let args = Args::parse_from(["notion-books", "--refresh-covers", "--since", "2024-01-31"]);
+        assert_eq!(args.since.as_deref(), Some("2024-01-31"));
+
+        assert!(parse_date("2024-1-31").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("31.01.2024").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn partial_isbns_are_recognized() {
+        assert_eq!(partial_isbn("978-04410").as_deref(), Some("97804410"));
+        assert_eq!(partial_isbn("04410").as_deref(), Some("04410"));
+        assert_eq!(partial_isbn("97804410135").as_deref(), Some("97804410135"));
+
+        // Too short, complete, implausible, or not an ISBN at all.
+        assert_eq!(partial_isbn("978"), None);
+        assert_eq!(partial_isbn("0441013597"), None);
+        assert_eq!(partial_isbn("9780441013593"), None);
+        assert_eq!(partial_isbn("12345678901"), None);
+        assert_eq!(partial_isbn("dune"), None);
+    }
+
+    #[test]
+    fn books_are_filtered_by_isbn_prefix() {
+        let mut dune = book_with_pages("Dune", None);
+        dune.identifiers = vec![
+            ("ISBN_13".to_string(), "9780441013593".to_string()),
+            ("ISBN_10".to_string(), "0441013597".to_string()),
+        ];
+        let mut messiah = book_with_pages("Dune Messiah", None);
+        messiah.identifiers = vec![("ISBN_13".to_string(), "9780593098233".to_string())];
+        let books = vec![dune, messiah];
+
+        assert_eq!(
+            titles(&filter_by_isbn_prefix(books.clone(), "04410")),
+            ["Dune"]
+        );
+        assert_eq!(
+            titles(&filter_by_isbn_prefix(books, "978")),
+            ["Dune", "Dune Messiah"]
+        );
+    }
+
+    #[test]
+    fn query_is_read() {
+        let mut ui = ScriptedUi::new(&["dune", "9780441013593"]);
+        assert_eq!(read_query(&mut ui, false).unwrap().as_deref(), Some("dune"));
+        assert_eq!(
+            read_query(&mut ui, true).unwrap().as_deref(),
+            Some("9780441013593")
+        );
+        assert_eq!(ui.output, ["Enter query: ", "Enter isbn: "]);
+    }
+
+    #[test]
+    fn empty_query_or_quit_command_quits() {
+        let mut ui = ScriptedUi::new(&["", ":q", "quit"]);
+        assert_eq!(read_query(&mut ui, false).unwrap(), None);
+        assert_eq!(read_query(&mut ui, true).unwrap(), None);
+        assert_eq!(read_query(&mut ui, false).unwrap(), None);
+    }
+
+    #[test]
+    fn end_of_input_quits() {
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(read_query(&mut ui, false).unwrap(), None);
+    }
+
+    #[test]
+    fn end_of_input_elsewhere_is_an_error() {
+        let mut ui = ScriptedUi::new(&[]);
+        let error = choose_book(&mut ui, &["Dune", "Dune Messiah"]).unwrap_err();
+        assert!(error.downcast_ref::<EndOfInput>().is_some());
+    }
+
+    #[test]
+    fn choose_create_with_existing_entries() {
+        let mut ui = ScriptedUi::new(&["0"]);
+        assert_eq!(
+            choose_action(&mut ui, &["Dune"], None, None).unwrap(),
+            Action::CreateNew
+        );
+    }
+
+    #[test]
+    fn choose_update_with_existing_entries() {
+        let mut ui = ScriptedUi::new(&["2"]);
+        assert_eq!(
+            choose_action(&mut ui, &["Dune", "Dune Messiah"], None, None).unwrap(),
+            Action::Update(1)
+        );
+        assert_eq!(
+            ui.output,
+            [
+                "Choose what you want to do:",
+                "0: Create a new entry",
+                "1: Update Dune",
+                "2: Update Dune Messiah",
+                "> "
+            ]
+        );
+    }
+
+    #[test]
+    fn suggested_entry_is_the_default() {
+        let mut ui = ScriptedUi::new(&[""]);
+        assert_eq!(
+            choose_action(&mut ui, &["Dune", "Dune Messiah"], Some(1), None).unwrap(),
+            Action::Update(1)
+        );
+        assert_eq!(
+            ui.output,
+            [
+                "Choose what you want to do:",
+                "0: Create a new entry",
+                "1: Update Dune",
+                "2: Update Dune Messiah (suggested)",
+                "> "
+            ]
+        );
+
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(
+            choose_action(
+                &mut ui,
+                &["Dune", "Dune Messiah"],
+                Some(1),
+                Some(OnMatch::Update)
+            )
+            .unwrap(),
+            Action::Update(1)
+        );
+    }
+
+    #[test]
+    fn confirm_create_without_existing_entries() {
+        let mut ui = ScriptedUi::new(&["y"]);
+        assert_eq!(
+            choose_action(&mut ui, &[] as &[&str], None, None).unwrap(),
+            Action::CreateNew
+        );
+    }
+
+    #[test]
+    fn decline_create_without_existing_entries() {
+        let mut ui = ScriptedUi::new(&["n"]);
+        assert_eq!(
+            choose_action(&mut ui, &[] as &[&str], None, None).unwrap(),
+            Action::Skip
+        );
+    }
+
+    #[test]
+    fn on_match_policy_skips_prompt() {
+        let mut ui = ScriptedUi::new(&[]);
+        let matches = ["Dune", "Dune Messiah"];
+        assert_eq!(
+            choose_action(&mut ui, &matches, None, Some(OnMatch::Update)).unwrap(),
+            Action::Update(0)
+        );
+        assert_eq!(
+            choose_action(&mut ui, &matches, None, Some(OnMatch::Create)).unwrap(),
+            Action::CreateNew
+        );
+        assert_eq!(
+            choose_action(&mut ui, &matches, None, Some(OnMatch::Skip)).unwrap(),
+            Action::Skip
+        );
+        assert!(ui.output.is_empty());
+    }
+
+    #[test]
+    fn on_match_policy_without_matches_creates() {
+        let mut ui = ScriptedUi::new(&[]);
+        assert_eq!(
+            choose_action(&mut ui, &[] as &[&str], None, Some(OnMatch::Skip)).unwrap(),
+            Action::CreateNew
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// A small markdown parser for user-provided descriptions, producing the same block representation
+// that Google Books descriptions end up as.
+//
+// Only a limited subset of markdown is supported:
+// - Headings, `# Heading` up to `### Heading` (Notion only has three levels).
+// - Bulleted list items, starting with `- ` or `* `.
+// - Numbered list items, starting with e.g. `1. `.
+// - Paragraphs, separated by blank lines. Lines within a paragraph are joined with a space.
+// - Bold (`**text**`) and italic (`*text*`) text, and both (`***text***`). Underscores are left
+//   alone, since they are much more likely to be part of a word than markup.
+
+use crate::descriptions::{Block, RichText, TextFragment, TextStyle};
+
+pub fn parse_markdown(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+
+    let flush_paragraph = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            blocks.push(Block::Paragraph(parse_inline(&paragraph.join(" "))));
+            paragraph.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        let block = if line.is_empty() {
+            flush_paragraph(&mut paragraph, &mut blocks);
+            continue;
+        } else if let Some((level, heading)) = parse_heading(line) {
+            Block::Heading(level, parse_inline(heading))
+        } else if let Some(item) = line.strip_prefix("- ").or(line.strip_prefix("* ")) {
+            Block::BulletedListItem(parse_inline(item.trim_start()))
+        } else if let Some(item) = strip_number_prefix(line) {
+            Block::NumberedListItem(parse_inline(item.trim_start()))
+        } else {
+            paragraph.push(line);
+            continue;
+        };
+
+        flush_paragraph(&mut paragraph, &mut blocks);
+        blocks.push(block);
+    }
+    flush_paragraph(&mut paragraph, &mut blocks);
+
+    blocks
+}
+
+fn parse_heading(line: &str) -> Option<(u8, &str)> {
+    let level = line.find(|c: char| c != '#')?;
+    let rest = line[level..].strip_prefix(' ')?;
+    (1..=3)
+        .contains(&level)
+        .then(|| (level as u8, rest.trim_start()))
+}
+
+fn strip_number_prefix(line: &str) -> Option<&str> {
+    let digits_end = line.find(|c: char| !c.is_ascii_digit())?;
+    if digits_end == 0 {
+        return None;
+    }
+    line[digits_end..].strip_prefix(". ")
+}
+
+fn parse_inline(text: &str) -> RichText {
+    let mut fragments = Vec::new();
+    let mut style = TextStyle {
+        bold: false,
+        italic: false,
+    };
+    let mut current = String::new();
+
+    let mut rest = text;
+    while let Some(pos) = rest.find('*') {
+        current.push_str(&rest[..pos]);
+        let stars = rest[pos..].len() - rest[pos..].trim_start_matches('*').len();
+
+        if !current.is_empty() {
+            fragments.push(TextFragment::new(&current, style));
+            current.clear();
+        }
+        match stars {
+            1 => style.italic = !style.italic,
+            2 => style.bold = !style.bold,
+            _ => {
+                style.bold = !style.bold;
+                style.italic = !style.italic;
+            }
+        }
+
+        rest = &rest[pos + stars..];
+    }
+    current.push_str(rest);
+    if !current.is_empty() {
+        fragments.push(TextFragment::new(current, style));
+    }
+
+    RichText { fragments }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> RichText {
+        RichText {
+            fragments: vec![TextFragment::new(text, TextStyle::unstyled())],
+        }
+    }
+
+    #[test]
+    fn paragraphs() {
+        assert_eq!(
+            parse_markdown("First paragraph\nstill first.\n\nSecond paragraph."),
+            vec![
+                Block::Paragraph(plain("First paragraph still first.")),
+                Block::Paragraph(plain("Second paragraph.")),
+            ]
+        );
+    }
+
+    #[test]
+    fn headings_and_lists() {
+        assert_eq!(
+            parse_markdown(
+                "# Notes\n- one\n* two\n\n## Steps\n1. first\n2. second\n#### Not a heading"
+            ),
+            vec![
+                Block::Heading(1, plain("Notes")),
+                Block::BulletedListItem(plain("one")),
+                Block::BulletedListItem(plain("two")),
+                Block::Heading(2, plain("Steps")),
+                Block::NumberedListItem(plain("first")),
+                Block::NumberedListItem(plain("second")),
+                Block::Paragraph(plain("#### Not a heading")),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            parse_markdown("Some **bold**, *italic* and ***both*** text."),
+            vec![Block::Paragraph(RichText {
+                fragments: vec![
+                    TextFragment::new("Some ", TextStyle::unstyled()),
+                    TextFragment::new("bold", TextStyle::bold()),
+                    TextFragment::new(", ", TextStyle::unstyled()),
+                    TextFragment::new("italic", TextStyle::italic()),
+                    TextFragment::new(" and ", TextStyle::unstyled()),
+                    TextFragment::new("both", TextStyle::bold_italic()),
+                    TextFragment::new(" text.", TextStyle::unstyled()),
+                ]
+            })]
+        );
+    }
+}
@@ -0,0 +1,465 @@
+// Scoring how likely it is that an existing database entry is the book found on Google Books, to
+// suggest which entry to update, and how well a book found on Google Books matches the query.
+//
+// Titles and authors are compared as sets of normalized words, so that differences in case,
+// punctuation and word order ("Herbert, Frank") don't matter. A matching ISBN settles it.
+
+use std::collections::HashSet;
+
+use crate::{gbooks::GBook, notion::NotionBookEntry};
+
+/// Entries scoring below this aren't suggested at all.
+const MIN_SUGGESTION_SCORE: f64 = 0.5;
+
+/// Books scoring at least this are taken to be the entry when back-filling ISBNs.
+const CONFIDENT_MATCH_SCORE: f64 = 0.8;
+
+const TITLE_WEIGHT: f64 = 0.7;
+const AUTHOR_WEIGHT: f64 = 0.3;
+
+/// How well `entry` matches `gbook`, from 0 (nothing in common) to 1 (same ISBN, or same title
+/// and authors).
+pub fn match_score(gbook: &GBook, entry: &NotionBookEntry) -> f64 {
+    if let (Some(a), Some(b)) = (&gbook.isbn, &entry.isbn) {
+        if normalize_isbn(a) == normalize_isbn(b) {
+            return 1.0;
+        }
+    }
+
+    let title = similarity(
+        &words(&gbook.title),
+        &words(strip_isbn_suffix(&entry.title)),
+    );
+    let authors = if gbook.authors.is_empty() || entry.authors.is_empty() {
+        // Without authors to compare, go by the title alone.
+        title
+    } else {
+        similarity(
+            &words(&gbook.authors.join(" ")),
+            &words(&entry.authors.join(" ")),
+        )
+    };
+
+    TITLE_WEIGHT * title + AUTHOR_WEIGHT * authors
+}
+
+/// The index of the entry that best matches `gbook`, if any matches well enough to suggest it.
+/// Ties go to the first entry.
+pub fn best_match(gbook: &GBook, entries: &[NotionBookEntry]) -> Option<usize> {
+    let mut best: Option<(usize, f64)> = None;
+    for (i, entry) in entries.iter().enumerate() {
+        let score = match_score(gbook, entry);
+        if score < MIN_SUGGESTION_SCORE {
+            continue;
+        }
+        match best {
+            Some((_, best_score)) if best_score >= score => {}
+            _ => best = Some((i, score)),
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// What searching Google Books for an entry without an ISBN turned up.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IsbnMatch<'a> {
+    /// All books matching the entry confidently have this ISBN.
+    Confident(&'a str),
+    /// Books with this many different ISBNs match the entry confidently, e.g. several editions.
+    Ambiguous(usize),
+    /// No book with an ISBN matches the entry confidently.
+    NoMatch,
+}
+
+/// Find the ISBN of `entry` among `books`, if there's exactly one that confidently matches.
+pub fn isbn_match<'a>(entry: &NotionBookEntry, books: &'a [GBook]) -> IsbnMatch<'a> {
+    let mut isbns: Vec<&str> = Vec::new();
+    for book in books {
+        if let Some(isbn) = &book.isbn {
+            if match_score(book, entry) >= CONFIDENT_MATCH_SCORE
+                && !isbns
+                    .iter()
+                    .any(|other| normalize_isbn(other) == normalize_isbn(isbn))
+            {
+                isbns.push(isbn.as_str());
+            }
+        }
+    }
+    match isbns[..] {
+        [] => IsbnMatch::NoMatch,
+        [isbn] => IsbnMatch::Confident(isbn),
+        _ => IsbnMatch::Ambiguous(isbns.len()),
+    }
+}
+
+/// How a list of too many entries matching a book was narrowed down.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Narrowed {
+    /// To the entries with the same ISBN as the book.
+    Isbn,
+    /// To the entries with the same title words as the book.
+    ExactTitle,
+    /// To the entries that match the book best.
+    BestMatches,
+}
+
+/// If there are more than `max` entries, narrow them down to those with the ISBN of `gbook`, or
+/// else to those with exactly its title, or else (or if that still leaves too many) to the `max`
+/// entries matching it best. Returns how they were narrowed, if they were.
+pub fn narrow_matches(
+    gbook: &GBook,
+    entries: &mut Vec<NotionBookEntry>,
+    max: usize,
+) -> Option<Narrowed> {
+    if entries.len() <= max {
+        return None;
+    }
+
+    if let Some(isbn) = gbook.isbn.as_deref().map(normalize_isbn) {
+        let same_isbn = |entry: &NotionBookEntry| {
+            entry.isbn.as_deref().map(normalize_isbn).as_ref() == Some(&isbn)
+        };
+        if entries.iter().any(same_isbn) {
+            entries.retain(same_isbn);
+            return Some(Narrowed::Isbn);
+        }
+    }
+
+    let title = words(&gbook.title);
+    let same_title = |entry: &NotionBookEntry| words(strip_isbn_suffix(&entry.title)) == title;
+    let same_title_count = entries.iter().filter(|entry| same_title(entry)).count();
+    if same_title_count > 0 && same_title_count <= max {
+        entries.retain(same_title);
+        return Some(Narrowed::ExactTitle);
+    }
+
+    // Stable sort, so entries matching equally well keep Notion's ordering.
+    entries.sort_by(|a, b| {
+        match_score(gbook, b)
+            .partial_cmp(&match_score(gbook, a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    entries.truncate(max);
+    Some(Narrowed::BestMatches)
+}
+
+/// How well `gbook` matches the search `query` it was found with, from 0 to 1: 1 if the query is
+/// one of its ISBNs, otherwise the share of words in the query that occur in its title or authors.
+pub fn query_score(query: &str, gbook: &GBook) -> f64 {
+    let isbn = normalize_isbn(query);
+    let is_isbn = gbook
+        .isbn
+        .iter()
+        .chain(gbook.identifiers.iter().map(|(_, identifier)| identifier))
+        .any(|identifier| normalize_isbn(identifier) == isbn);
+    if is_isbn {
+        return 1.0;
+    }
+
+    let query = words(query);
+    if query.is_empty() {
+        return 0.0;
+    }
+    let book = words(&format!("{} {}", gbook.title, gbook.authors.join(" ")));
+    query.intersection(&book).count() as f64 / query.len() as f64
+}
+
+/// Remove an ISBN added to the end of a title with `--title-with-isbn`, like in
+/// "Dune [9780441013593]".
+pub fn strip_isbn_suffix(title: &str) -> &str {
+    let stripped = title.trim_end().strip_suffix(']').and_then(|rest| {
+        let (title, isbn) = rest.rsplit_once(" [")?;
+        let is_isbn = !isbn.is_empty()
+            && isbn
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '-' || c == 'X');
+        is_isbn.then_some(title)
+    });
+    stripped.unwrap_or(title)
+}
+
+/// `authors` without duplicates: Names that only differ in case, punctuation or spacing, like
+/// "J.R.R. Tolkien" and "J. R. R. Tolkien", and a bare surname like "Tolkien" if exactly one other
+/// author has it. Of those, the first full name is kept, where the first of them was listed.
+pub fn dedupe_authors(authors: &[String]) -> Vec<String> {
+    let mut deduped: Vec<String> = Vec::new();
+    for author in authors {
+        let mut author = author;
+        if let [surname] = name_words(author).as_slice() {
+            let full_names = authors
+                .iter()
+                .filter(|other| {
+                    let words = name_words(other);
+                    words.len() > 1 && words.last() == Some(surname)
+                })
+                .collect::<Vec<_>>();
+            // Different spellings of the same full name still count as one.
+            if let Some(full_name) = full_names.first() {
+                let key = name_words(full_name).concat();
+                if full_names
+                    .iter()
+                    .all(|other| name_words(other).concat() == key)
+                {
+                    author = full_name;
+                }
+            }
+        }
+        let key = name_words(author).concat();
+        if !deduped
+            .iter()
+            .any(|known| name_words(known).concat() == key)
+        {
+            deduped.push(author.clone());
+        }
+    }
+    deduped
+}
+
+/// The lowercased words of a name, in order, ignoring punctuation. Initials written together
+/// ("J.R.R.") are separate words, just like those written apart ("J. R. R.").
+fn name_words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// The lowercased words of `text`, ignoring punctuation.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard similarity: the share of words that occur in both sets, out of all words.
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gbook(title: &str, authors: &[&str], isbn: Option<&str>) -> GBook {
+        GBook {
+            authors: authors.iter().map(|a| a.to_string()).collect(),
+            isbn: isbn.map(str::to_string),
+            ..GBook::new(title)
+        }
+    }
+
+    fn entry(title: &str, authors: &[&str], isbn: Option<&str>) -> NotionBookEntry {
+        NotionBookEntry {
+            authors: authors.iter().map(|a| a.to_string()).collect(),
+            author_ids: vec![None; authors.len()],
+            isbn: isbn.map(str::to_string),
+            ..NotionBookEntry::new(title)
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn duplicate_authors_are_removed() {
+        assert_eq!(
+            dedupe_authors(&strings(&["Neil Gaiman", "Terry Pratchett", "Neil Gaiman"])),
+            ["Neil Gaiman", "Terry Pratchett"]
+        );
+        assert_eq!(
+            dedupe_authors(&strings(&[
+                "J.R.R. Tolkien",
+                "J. R. R. Tolkien",
+                "j.r.r. tolkien "
+            ])),
+            ["J.R.R. Tolkien"]
+        );
+    }
+
+    #[test]
+    fn bare_surnames_are_merged_into_full_names() {
+        assert_eq!(
+            dedupe_authors(&strings(&["Tolkien", "J.R.R. Tolkien"])),
+            ["J.R.R. Tolkien"]
+        );
+        assert_eq!(
+            dedupe_authors(&strings(&["J.R.R. Tolkien", "Tolkien", "Alan Lee"])),
+            ["J.R.R. Tolkien", "Alan Lee"]
+        );
+        assert_eq!(
+            dedupe_authors(&strings(&["Tolkien", "J. R. R. Tolkien", "J.R.R. Tolkien"])),
+            ["J. R. R. Tolkien"]
+        );
+        // With several full names to choose from, the surname is left alone.
+        assert_eq!(
+            dedupe_authors(&strings(&[
+                "J.R.R. Tolkien",
+                "Christopher Tolkien",
+                "Tolkien"
+            ])),
+            ["J.R.R. Tolkien", "Christopher Tolkien", "Tolkien"]
+        );
+    }
+
+    #[test]
+    fn same_isbn_is_a_perfect_match() {
+        let found = gbook("Dune", &["Frank Herbert"], Some("9780441013593"));
+        let existing = entry("Dune (Deluxe Edition)", &[], Some("978-0-441-01359-3"));
+        assert_eq!(match_score(&found, &existing), 1.0);
+    }
+
+    #[test]
+    fn title_and_authors_are_compared_loosely() {
+        let found = gbook("Good Omens", &["Terry Pratchett", "Neil Gaiman"], None);
+        let existing = entry("good omens!", &["Neil Gaiman", "Terry Pratchett"], None);
+        assert_eq!(match_score(&found, &existing), 1.0);
+
+        let other = entry("Good Omens: The Script Book", &["Neil Gaiman"], None);
+        let score = match_score(&found, &other);
+        assert!(score > 0.0 && score < 1.0, "{score}");
+    }
+
+    #[test]
+    fn isbn_suffix_is_ignored() {
+        assert_eq!(strip_isbn_suffix("Dune [9780441013593]"), "Dune");
+        assert_eq!(strip_isbn_suffix("Dune [0-441-01359-X]"), "Dune");
+        assert_eq!(strip_isbn_suffix("Dune [Deluxe]"), "Dune [Deluxe]");
+        assert_eq!(strip_isbn_suffix("Dune"), "Dune");
+
+        let found = gbook("Dune", &["Frank Herbert"], None);
+        let existing = entry("Dune [9780441013593]", &["Frank Herbert"], None);
+        assert_eq!(match_score(&found, &existing), 1.0);
+    }
+
+    #[test]
+    fn best_match_is_suggested() {
+        let found = gbook("Dune Messiah", &["Frank Herbert"], Some("9780593098233"));
+        let entries = [
+            entry("Dune", &["Frank Herbert"], Some("9780441013593")),
+            entry("Dune Messiah", &["Frank Herbert"], None),
+            entry("Children of Dune", &["Frank Herbert"], None),
+        ];
+        assert_eq!(best_match(&found, &entries), Some(1));
+    }
+
+    #[test]
+    fn query_matching_an_isbn_is_certain() {
+        let mut found = gbook("Dune", &["Frank Herbert"], Some("9780441013593"));
+        assert_eq!(query_score("978-0441013593", &found), 1.0);
+
+        found.identifiers = vec![("ISBN_10".to_string(), "0441013597".to_string())];
+        assert_eq!(query_score("0441013597", &found), 1.0);
+        assert_eq!(query_score("0441172717", &found), 0.0);
+    }
+
+    #[test]
+    fn query_words_are_looked_up_in_title_and_authors() {
+        let found = gbook("Dune Messiah", &["Frank Herbert"], None);
+        assert_eq!(query_score("dune herbert", &found), 1.0);
+        assert_eq!(query_score("Dune: House Atreides", &found), 1.0 / 3.0);
+        assert_eq!(query_score("", &found), 0.0);
+    }
+
+    #[test]
+    fn isbn_is_matched_only_if_unambiguous() {
+        let existing = entry("Dune", &["Frank Herbert"], None);
+        let dune = gbook("Dune", &["Frank Herbert"], Some("9780441013593"));
+        let same_isbn = gbook("Dune", &["Herbert, Frank"], Some("978-0-441-01359-3"));
+        let messiah = gbook("Dune Messiah", &["Frank Herbert"], Some("9780593098233"));
+        let no_isbn = gbook("Dune", &["Frank Herbert"], None);
+
+        assert_eq!(
+            isbn_match(&existing, &[dune.clone(), same_isbn, messiah, no_isbn]),
+            IsbnMatch::Confident("9780441013593")
+        );
+
+        let other_edition = gbook("Dune", &["Frank Herbert"], Some("9780340960196"));
+        assert_eq!(
+            isbn_match(&existing, &[dune, other_edition]),
+            IsbnMatch::Ambiguous(2)
+        );
+
+        let messiah = gbook("Dune Messiah", &["Frank Herbert"], Some("9780593098233"));
+        assert_eq!(isbn_match(&existing, &[messiah]), IsbnMatch::NoMatch);
+        assert_eq!(isbn_match(&existing, &[]), IsbnMatch::NoMatch);
+    }
+
+    #[test]
+    fn poor_matches_are_not_suggested() {
+        let found = gbook("Dune", &["Frank Herbert"], None);
+        let entries = [entry(
+            "The Left Hand of Darkness",
+            &["Ursula K. Le Guin"],
+            None,
+        )];
+        assert_eq!(best_match(&found, &entries), None);
+        assert_eq!(best_match(&found, &[]), None);
+    }
+
+    fn titles(entries: &[NotionBookEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.title.as_str()).collect()
+    }
+
+    #[test]
+    fn few_matches_are_not_narrowed() {
+        let found = gbook("Dune", &["Frank Herbert"], None);
+        let mut entries = vec![entry("Dune", &[], None), entry("Dune Messiah", &[], None)];
+        assert_eq!(narrow_matches(&found, &mut entries, 2), None);
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn matches_are_narrowed_by_isbn_then_title() {
+        let found = gbook("Dune", &["Frank Herbert"], Some("978-0441013593"));
+        let all = vec![
+            entry("Dune Messiah", &[], None),
+            entry("Dune", &[], Some("9780441013593")),
+            entry("Dune", &[], None),
+            entry("Children of Dune", &[], None),
+        ];
+
+        let mut entries = all.clone();
+        assert_eq!(
+            narrow_matches(&found, &mut entries, 2),
+            Some(Narrowed::Isbn)
+        );
+        assert_eq!(titles(&entries), ["Dune"]);
+        assert_eq!(entries[0].isbn.as_deref(), Some("9780441013593"));
+
+        let found = gbook("Dune", &["Frank Herbert"], None);
+        let mut entries = all;
+        assert_eq!(
+            narrow_matches(&found, &mut entries, 2),
+            Some(Narrowed::ExactTitle)
+        );
+        assert_eq!(titles(&entries), ["Dune", "Dune"]);
+    }
+
+    #[test]
+    fn matches_are_narrowed_to_the_best() {
+        let found = gbook("Messiah", &[], None);
+        let mut entries = vec![
+            entry("Dune", &[], None),
+            entry("Dune Messiah", &[], None),
+            entry("The Messiah", &[], None),
+        ];
+        assert_eq!(
+            narrow_matches(&found, &mut entries, 2),
+            Some(Narrowed::BestMatches)
+        );
+        assert_eq!(titles(&entries), ["Dune Messiah", "The Messiah"]);
+    }
+}
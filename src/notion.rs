@@ -1,26 +1,589 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
 
-use futures::future;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
-use reqwest::{Client, Method, RequestBuilder};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use miette::{miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde_derive::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 use url::Url;
 
-use crate::descriptions::{RichText, TextFragment};
+use crate::{
+    dates::{DateDisplay, DisplayDates},
+    descriptions::{Block, RichText, TextFragment},
+    locale::Locale,
+    titles::TitleTemplate,
+};
 
 #[derive(Debug)]
 pub struct Notion {
     integration_token: String,
     client: Client,
+    user_agent: Option<String>,
+    rate_limiter: Option<RateLimiter>,
+    #[cfg(test)]
+    mock: Option<MockApi>,
 }
 
 #[derive(Debug)]
 pub struct Database<'notion> {
     notion: &'notion Notion,
     database_id: String,
+    options: DatabaseOptions,
+    /// The type of each property of the database, by name. This is `None` if Notion didn't tell us
+    /// about the properties, in which case we just don't check them before writing.
+    schema: Option<HashMap<String, String>>,
 }
 
+#[derive(Debug, Default)]
+pub struct DatabaseOptions {
+    pub properties: PropertyNames,
+    /// If set, authors are stored as a relation to pages in this database instead of as a
+    /// multi-select.
+    pub authors_database_id: Option<String>,
+    pub ownership: OwnershipConfig,
+    pub publisher: PublisherSanitization,
+    pub genres: GenreMapping,
+    /// If set, the reading status is read and written, e.g. for `--reading-status`.
+    pub reading_status: Option<ReadingStatusConfig>,
+    /// How many page bodies to fetch at once when searching. Defaults to
+    /// `DEFAULT_DESCRIPTION_CONCURRENCY`.
+    pub description_concurrency: Option<usize>,
+    /// Write descriptions to a child page titled "Description" instead of the page body itself.
+    pub description_child_page: bool,
+    /// The value of the Type select for books. Defaults to "Book".
+    pub book_type: Option<String>,
+    /// Who new entries are added by: A name for a select, or the ID of a Notion user for a people
+    /// property.
+    pub added_by: Option<String>,
+    /// How ISBNs are joined in the ISBNs property, if one is configured.
+    pub isbn_separator: IsbnSeparator,
+    /// How the Name of new entries is made from the book. Defaults to just its title.
+    pub title_template: Option<TitleTemplate>,
+}
+
+impl DatabaseOptions {
+    pub fn book_type(&self) -> &str {
+        self.book_type.as_deref().unwrap_or("Book")
+    }
+}
+
+const DEFAULT_DESCRIPTION_CONCURRENCY: usize = 5;
+
+/// Names of optional database properties, for those that can be configured.
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PropertyNames {
+    #[knuffel(child, unwrap(argument))]
+    notes: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    edition: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    original_year: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    format: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    dimensions: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    raw_description: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    series_position: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    authors_text: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    categories: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    source: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    epub: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    pdf: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    last_synced: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    volume_id: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    self_link: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    batch: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    added_by: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    isbns: Option<String>,
+}
+
+impl PropertyNames {
+    pub fn notes(&self) -> &str {
+        self.notes.as_deref().unwrap_or("Notes")
+    }
+
+    /// Editions are only stored if a property for them is configured.
+    pub fn edition(&self) -> Option<&str> {
+        self.edition.as_deref()
+    }
+
+    /// Original publication years are only stored if a (number) property for them is configured.
+    pub fn original_year(&self) -> Option<&str> {
+        self.original_year.as_deref()
+    }
+
+    /// Formats are only stored if a (select) property for them is configured.
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    /// Dimensions are only stored if a (text) property for them is configured.
+    pub fn dimensions(&self) -> Option<&str> {
+        self.dimensions.as_deref()
+    }
+
+    /// The unparsed Google Books description is only stored if a (text) property for it is
+    /// configured.
+    pub fn raw_description(&self) -> Option<&str> {
+        self.raw_description.as_deref()
+    }
+
+    /// Series positions are only stored if a (number) property for them is configured.
+    pub fn series_position(&self) -> Option<&str> {
+        self.series_position.as_deref()
+    }
+
+    /// All authors joined into one string are only stored if a (text) property for them is
+    /// configured, in addition to the Authors property.
+    pub fn authors_text(&self) -> Option<&str> {
+        self.authors_text.as_deref()
+    }
+
+    /// All ISBNs of a book (like both the ISBN-13 and ISBN-10) are only stored if a (text)
+    /// property for them is configured, in addition to the ISBN property.
+    pub fn isbns(&self) -> Option<&str> {
+        self.isbns.as_deref()
+    }
+
+    /// Categories are only stored if a (multi-select) property for them is configured.
+    pub fn categories(&self) -> Option<&str> {
+        self.categories.as_deref()
+    }
+
+    /// Where the metadata came from is only stored if a (select) property for it is configured.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Whether an EPUB or PDF can be downloaded is only stored if (checkbox) properties for them
+    /// are configured.
+    pub fn epub(&self) -> Option<&str> {
+        self.epub.as_deref()
+    }
+
+    pub fn pdf(&self) -> Option<&str> {
+        self.pdf.as_deref()
+    }
+
+    /// When an entry was last updated from Google Books is only stored if a (date) property for it
+    /// is configured.
+    pub fn last_synced(&self) -> Option<&str> {
+        self.last_synced.as_deref()
+    }
+
+    /// The Google Books volume ID and self link are only stored if (text and URL) properties for
+    /// them are configured.
+    pub fn volume_id(&self) -> Option<&str> {
+        self.volume_id.as_deref()
+    }
+
+    pub fn self_link(&self) -> Option<&str> {
+        self.self_link.as_deref()
+    }
+
+    pub fn batch(&self) -> &str {
+        self.batch.as_deref().unwrap_or("Batch")
+    }
+
+    pub fn added_by(&self) -> &str {
+        self.added_by.as_deref().unwrap_or("Added By")
+    }
+}
+
+/// How ownership is represented in the database: A select property, with one value meaning the
+/// book is owned and optionally another one meaning it isn't, or a checkbox.
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct OwnershipConfig {
+    #[knuffel(child, unwrap(argument))]
+    property: Option<String>,
+    /// The property is a checkbox, checked for owned books. The values are ignored then.
+    #[knuffel(child)]
+    checkbox: bool,
+    #[knuffel(child, unwrap(argument))]
+    owned: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    not_owned: Option<String>,
+}
+
+impl OwnershipConfig {
+    pub fn property(&self) -> &str {
+        self.property.as_deref().unwrap_or("Ownership")
+    }
+
+    pub fn is_checkbox(&self) -> bool {
+        self.checkbox
+    }
+
+    pub fn owned(&self) -> &str {
+        self.owned.as_deref().unwrap_or("Own")
+    }
+
+    /// If this isn't set, the ownership property is left alone for books that aren't owned.
+    pub fn not_owned(&self) -> Option<&str> {
+        self.not_owned.as_deref()
+    }
+}
+
+/// How all ISBNs of a book are joined into the text of the ISBNs property.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum IsbnSeparator {
+    /// "9780441013593, 0441013597"
+    #[default]
+    Comma,
+    /// One ISBN per line.
+    Newline,
+}
+
+impl IsbnSeparator {
+    /// Parse a separator by its name, "comma" or "newline".
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "comma" => Ok(IsbnSeparator::Comma),
+            "newline" => Ok(IsbnSeparator::Newline),
+            _ => Err(miette!(
+                "Unsupported ISBN separator '{}'; supported are comma and newline",
+                name
+            )),
+        }
+    }
+
+    fn join(self, isbns: &[String]) -> String {
+        match self {
+            IsbnSeparator::Comma => isbns.join(", "),
+            IsbnSeparator::Newline => isbns.join("\n"),
+        }
+    }
+}
+
+/// Split the text of the ISBNs property back into ISBNs. Both separators are accepted, so that
+/// changing the separator doesn't break reading existing entries.
+fn split_isbns(text: &str) -> Vec<String> {
+    text.split([',', '\n'])
+        .map(str::trim)
+        .filter(|isbn| !isbn.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// How the reading status (like "To Read" or "Finished") is stored: In a select property, or in a
+/// property of Notion's status type, which groups its options into to-do, in progress and
+/// complete.
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ReadingStatusConfig {
+    #[knuffel(child, unwrap(argument))]
+    property: Option<String>,
+    /// The property is a status instead of a select.
+    #[knuffel(child)]
+    status: bool,
+}
+
+impl ReadingStatusConfig {
+    pub fn property(&self) -> &str {
+        self.property.as_deref().unwrap_or("Reading Status")
+    }
+
+    /// The Notion type of the property. Both types have the option under `name`.
+    fn property_type(&self) -> &'static str {
+        if self.status {
+            "status"
+        } else {
+            "select"
+        }
+    }
+}
+
+/// How publisher names are cleaned up before storing them. By default, names are only trimmed and
+/// have their commas removed (which Notion doesn't allow in select options).
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PublisherSanitization {
+    /// Replace runs of whitespace with a single space.
+    #[knuffel(child)]
+    collapse_whitespace: bool,
+    /// Remove punctuation at the end of names, like in "Penguin Books Ltd.".
+    #[knuffel(child)]
+    strip_trailing_punctuation: bool,
+    /// Names to replace with another, e.g. to merge different spellings of the same publisher.
+    #[knuffel(children(name = "alias"))]
+    #[serde(rename = "alias")]
+    aliases: Vec<PublisherAlias>,
+}
+
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, PartialEq)]
+pub struct PublisherAlias {
+    #[knuffel(argument)]
+    name: String,
+    #[knuffel(argument)]
+    replacement: String,
+}
+
+impl PublisherSanitization {
+    pub fn sanitize(&self, publisher: &str) -> String {
+        let mut name = publisher.trim().to_string();
+        if self.collapse_whitespace {
+            name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        if self.strip_trailing_punctuation {
+            name = name
+                .trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace())
+                .to_string();
+        }
+        if let Some(alias) = self.aliases.iter().find(|alias| alias.name == name) {
+            name = alias.replacement.clone();
+        }
+        name.replace(',', "")
+    }
+}
+
+/// How Google Books categories (like "Juvenile Fiction / Fantasy & Magic") are turned into genres
+/// before storing them. By default, categories are stored as they are.
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct GenreMapping {
+    /// Categories to replace with a genre if they are exactly the same.
+    #[knuffel(children(name = "exact"))]
+    exact: Vec<GenreRule>,
+    /// Categories to replace with a genre if they contain some text, ignoring case. These are only
+    /// tried for categories without an exact match, in order.
+    #[knuffel(children(name = "contains"))]
+    contains: Vec<GenreRule>,
+    /// Leave out categories that no rule matches, instead of storing them as they are.
+    #[knuffel(child)]
+    drop_unmapped: bool,
+}
+
+#[derive(knuffel::Decode, Deserialize, Serialize, Debug, PartialEq)]
+pub struct GenreRule {
+    #[knuffel(argument)]
+    category: String,
+    #[knuffel(argument)]
+    genre: String,
+}
+
+impl GenreMapping {
+    /// The genres for `categories`, without duplicates. Categories that already are one of the
+    /// genres are kept as they are, so entries read back from the database keep theirs.
+    pub fn map(&self, categories: &[String]) -> Vec<String> {
+        let mut genres = Vec::new();
+        for category in categories {
+            let category = category.trim();
+            let genre = if self.is_genre(category) {
+                Some(category)
+            } else {
+                self.rule_for(category)
+                    .map(|rule| rule.genre.as_str())
+                    .or_else(|| (!self.drop_unmapped).then_some(category))
+            };
+            if let Some(genre) = genre {
+                if !genres.iter().any(|known| known == genre) {
+                    genres.push(genre.to_string());
+                }
+            }
+        }
+        genres
+    }
+
+    fn is_genre(&self, category: &str) -> bool {
+        self.exact
+            .iter()
+            .chain(&self.contains)
+            .any(|rule| rule.genre == category)
+    }
+
+    fn rule_for(&self, category: &str) -> Option<&GenreRule> {
+        let lowercase = category.to_lowercase();
+        self.exact
+            .iter()
+            .find(|rule| rule.category == category)
+            .or_else(|| {
+                self.contains
+                    .iter()
+                    .find(|rule| lowercase.contains(&rule.category.to_lowercase()))
+            })
+    }
+}
+
+/// A filter for querying database entries, see
+/// https://developers.notion.com/reference/post-database-query-filter.
 #[derive(Debug, Clone)]
+pub enum Filter {
+    Property {
+        property: String,
+        /// The Notion type of the property, e.g. `rich_text` or `select`.
+        ty: String,
+        condition: Condition,
+    },
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Equals(String),
+    DoesNotEqual(String),
+    Contains(String),
+    IsEmpty,
+    IsNotEmpty,
+    /// For checkboxes, which are compared to a boolean instead of a string.
+    Checked(bool),
+    /// For dates, given as YYYY-MM-DD.
+    Before(String),
+}
+
+impl Filter {
+    pub fn property(property: &str, ty: &str, condition: Condition) -> Self {
+        Self::Property {
+            property: property.to_string(),
+            ty: ty.to_string(),
+            condition,
+        }
+    }
+
+    pub fn title_contains(title: &str) -> Self {
+        Self::property("title", "title", Condition::Contains(title.to_string()))
+    }
+
+    pub fn missing_isbn() -> Self {
+        Self::property("ISBN", "rich_text", Condition::IsEmpty)
+    }
+
+    pub fn isbn_equals(isbn: &str) -> Self {
+        Self::property("ISBN", "rich_text", Condition::Equals(isbn.to_string()))
+    }
+
+    pub fn has_isbn() -> Self {
+        Self::property("ISBN", "rich_text", Condition::IsNotEmpty)
+    }
+
+    /// Entries whose date `property` is empty or before `date`.
+    pub fn date_before_or_empty(property: &str, date: &str) -> Self {
+        Self::Or(vec![
+            Self::property(property, "date", Condition::IsEmpty),
+            Self::property(property, "date", Condition::Before(date.to_string())),
+        ])
+    }
+
+    pub fn not_owned(ownership: &OwnershipConfig) -> Self {
+        if ownership.is_checkbox() {
+            return Self::property(ownership.property(), "checkbox", Condition::Checked(false));
+        }
+        Self::Or(vec![
+            Self::property(ownership.property(), "select", Condition::IsEmpty),
+            Self::property(
+                ownership.property(),
+                "select",
+                Condition::DoesNotEqual(ownership.owned().to_string()),
+            ),
+        ])
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Filter::Property {
+                property,
+                ty,
+                condition,
+            } => {
+                let condition = match condition {
+                    Condition::Equals(value) => json!({ "equals": value }),
+                    Condition::DoesNotEqual(value) => json!({ "does_not_equal": value }),
+                    Condition::Contains(value) => json!({ "contains": value }),
+                    Condition::IsEmpty => json!({ "is_empty": true }),
+                    Condition::IsNotEmpty => json!({ "is_not_empty": true }),
+                    Condition::Checked(checked) => json!({ "equals": checked }),
+                    Condition::Before(date) => json!({ "before": date }),
+                };
+                json!({ "property": property, ty: condition })
+            }
+            Filter::And(filters) => {
+                json!({ "and": filters.iter().map(Filter::to_json).collect::<Vec<_>>() })
+            }
+            Filter::Or(filters) => {
+                json!({ "or": filters.iter().map(Filter::to_json).collect::<Vec<_>>() })
+            }
+        }
+    }
+}
+
+/// An unsuccessful response from the Notion API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error {}:\n{:#?}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ApiError {}
+impl Diagnostic for ApiError {}
+
+impl ApiError {
+    /// Whether the request might succeed if it is simply tried again.
+    pub fn is_transient(&self) -> bool {
+        self.status.is_server_error()
+            || self.status == StatusCode::TOO_MANY_REQUESTS
+            || self.status == StatusCode::CONFLICT
+    }
+
+    /// If this is one of the errors Notion gives when the integration can't access a database,
+    /// what the user should do about it.
+    fn access_problem(&self) -> Option<&'static str> {
+        match (self.status, self.body["code"].as_str()) {
+            (StatusCode::NOT_FOUND, Some("object_not_found"))
+            | (StatusCode::FORBIDDEN, Some("restricted_resource")) => Some(
+                "The integration can't access this database. Share the database with your \
+                 integration in Notion: open the database, click the ••• menu in the top right, \
+                 and add the integration under Connections.",
+            ),
+            (StatusCode::UNAUTHORIZED, _) => Some(
+                "Notion rejected the integration token. Check `integration-token` in the config \
+                 file against the token shown for your integration on \
+                 https://www.notion.so/my-integrations.",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Replace the cryptic errors for a database the integration can't access with guidance on how to
+/// fix that.
+fn explain_access_error(error: miette::Report) -> miette::Report {
+    let problem = error
+        .downcast_ref::<ApiError>()
+        .and_then(ApiError::access_problem);
+    match problem {
+        Some(problem) => miette!("{}", problem),
+        None => error,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct NotionBookEntry {
     pub id: Option<String>,
     pub title: String,
@@ -29,9 +592,44 @@ pub struct NotionBookEntry {
     pub publisher: Option<String>,
     pub published_date: Option<String>,
     pub isbn: Option<String>,
+    /// All ISBNs of the book, like both the ISBN-13 and ISBN-10, those of the preferred type first.
+    pub isbns: Vec<String>,
     pub cover_url: Option<String>,
+    /// Whether the page cover is a file uploaded to Notion, rather than an external URL. Such a
+    /// cover was set by hand, so `cover_url` is `None` and the cover is left alone.
+    pub cover_uploaded: bool,
     pub author_ids: Vec<Option<String>>,
     pub publisher_id: Option<String>,
+    pub notes: Option<String>,
+    pub edition: Option<String>,
+    /// The year the book was originally published, if it is a reprint.
+    pub original_year: Option<u16>,
+    pub format: Option<String>,
+    pub dimensions: Option<String>,
+    pub series_position: Option<f64>,
+    /// The genres of the book, from its Google Books categories by way of `GenreMapping`.
+    pub categories: Vec<String>,
+    /// The name of the service the metadata came from, like "Google Books".
+    pub source: Option<String>,
+    pub reading_status: Option<String>,
+    pub epub_available: Option<bool>,
+    pub pdf_available: Option<bool>,
+    /// The date (YYYY-MM-DD) the entry was last created or updated from Google Books.
+    pub last_synced: Option<String>,
+    /// The Google Books volume the entry was created or updated from, to find exactly the same
+    /// edition again.
+    pub volume_id: Option<String>,
+    pub self_link: Option<String>,
+    /// A tag for the import batch this entry was created in. This is only ever written, never read
+    /// back.
+    pub batch_tag: Option<String>,
+    /// The description as received from Google Books. Like the batch tag, this is only ever
+    /// written.
+    pub raw_description: Option<String>,
+    /// The IDs of the users who created and last edited the page. Like the ID, these are only ever
+    /// read.
+    pub created_by: Option<String>,
+    pub last_edited_by: Option<String>,
 
     // Description is special in that we do not have sufficient code to correctly read a whole
     // page body and set it again when editing an entry, since we only support setting a single
@@ -40,19 +638,184 @@ pub struct NotionBookEntry {
     // To avoid deleting data, only ever *set* a description when editing an entry, if there was
     // no page body at all before.
     pub had_original_description: bool,
-    pub description: Option<RichText>,
+    pub description: Option<Vec<Block>>,
+}
+
+#[cfg(test)]
+impl NotionBookEntry {
+    /// A new entry with only a title, to fill in the rest with struct update syntax.
+    pub fn new(title: &str) -> Self {
+        Self {
+            id: None,
+            title: title.to_string(),
+            owned: false,
+            authors: vec![],
+            publisher: None,
+            published_date: None,
+            isbn: None,
+            isbns: vec![],
+            cover_url: None,
+            cover_uploaded: false,
+            author_ids: vec![],
+            publisher_id: None,
+            notes: None,
+            edition: None,
+            original_year: None,
+            format: None,
+            dimensions: None,
+            series_position: None,
+            categories: vec![],
+            source: None,
+            reading_status: None,
+            epub_available: None,
+            pdf_available: None,
+            last_synced: None,
+            volume_id: None,
+            self_link: None,
+            batch_tag: None,
+            raw_description: None,
+            created_by: None,
+            last_edited_by: None,
+            had_original_description: false,
+            description: None,
+        }
+    }
 }
 
+/// Notion asks for an average of no more than three requests per second.
+pub const DEFAULT_REQUESTS_PER_SECOND: u32 = 3;
+
 impl Notion {
     pub fn new(integration_token: String) -> Self {
         Self {
             integration_token,
             client: Client::new(),
+            user_agent: None,
+            rate_limiter: Some(RateLimiter::new(DEFAULT_REQUESTS_PER_SECOND)),
+            #[cfg(test)]
+            mock: None,
+        }
+    }
+
+    /// A `Notion` that doesn't send any requests, but answers them using `handler` instead.
+    #[cfg(test)]
+    fn mock(
+        handler: impl Fn(&Method, &str, Option<&Value>) -> (StatusCode, Value) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            mock: Some(MockApi {
+                handler: Box::new(handler),
+                calls: Default::default(),
+            }),
+            rate_limiter: None,
+            ..Self::new(String::new())
+        }
+    }
+
+    /// Identify ourselves with this User-Agent header in all requests.
+    pub fn with_user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    /// Start at most `requests_per_second` requests per second. Zero disables pacing.
+    pub fn with_rate_limit(mut self, requests_per_second: u32) -> Self {
+        self.rate_limiter =
+            (requests_per_second > 0).then(|| RateLimiter::new(requests_per_second));
+        self
+    }
+
+    pub async fn database(
+        &self,
+        database_id: String,
+        options: DatabaseOptions,
+    ) -> Result<Database<'_>> {
+        Database::get(self, database_id, options).await
+    }
+
+    /// Look up the name of the user with the given ID. This needs the integration to have the
+    /// capability to read user information.
+    pub async fn user_name(&self, id: &str) -> Result<String> {
+        let response = self
+            .request(Method::GET, &format!("/users/{id}"), |req| req)
+            .await?;
+        response["name"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| miette!("No name for user {} in Notion API response!", id))
+    }
+
+    /// Find the ID of the database titled `name` (ignoring case) among the databases shared with
+    /// the integration. Fails if there is no such database, or more than one.
+    pub async fn find_database_by_name(&self, name: &str) -> Result<String> {
+        let databases = self
+            .query_all(
+                "/search",
+                json!({
+                    "query": name,
+                    "filter": { "property": "object", "value": "database" },
+                }),
+            )
+            .await?;
+
+        // The search is fuzzy, so only keep exact matches.
+        let mut matches = Vec::new();
+        for database in &databases {
+            let title = plain_text(
+                database["title"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            );
+            if title.trim().eq_ignore_ascii_case(name.trim()) {
+                if let Some(id) = database["id"].as_str() {
+                    matches.push(id.to_string());
+                }
+            }
+        }
+
+        match matches.len() {
+            0 => Err(miette!(
+                "No database named \"{}\" found. Check the name, and make sure the database \
+                 is shared with your integration (under Connections in the database's menu).",
+                name
+            )),
+            1 => Ok(matches.remove(0)),
+            _ => Err(miette!(
+                "There are several databases named \"{name}\" ({}). Set `database-id` to the one \
+                 you want to use instead.",
+                matches.join(", ")
+            )),
         }
     }
 
-    pub async fn database(&self, database_id: String) -> Result<Database<'_>> {
-        Database::get(self, database_id).await
+    /// POST `body` to a paginated endpoint, following `next_cursor` until all results are in.
+    async fn query_all(&self, endpoint: &str, body: Value) -> Result<Vec<Value>> {
+        let mut results = Vec::new();
+        let mut start_cursor = None;
+
+        loop {
+            let mut body = body.clone();
+            if let Some(cursor) = start_cursor {
+                body["start_cursor"] = cursor;
+            }
+
+            let mut response = self
+                .request(Method::POST, endpoint, |req| req.json(&body))
+                .await?;
+
+            let page = response["results"]
+                .as_array_mut()
+                .ok_or_else(|| miette!("No results array in Notion API response!"))?;
+            results.append(page);
+
+            if response["has_more"].as_bool() != Some(true) {
+                break;
+            }
+            start_cursor = Some(response["next_cursor"].take());
+        }
+
+        Ok(results)
     }
 
     async fn request<F>(&self, method: Method, endpoint: &str, f: F) -> Result<Value>
@@ -61,7 +824,7 @@ impl Notion {
     {
         let url = Url::parse(&format!("https://api.notion.com/v1{endpoint}")).unwrap();
 
-        let default_request = self
+        let mut default_request = self
             .client
             .request(method, url)
             .header(
@@ -70,8 +833,21 @@ impl Notion {
             )
             .header("Content-Type", "application/json")
             .header("Notion-Version", "2022-02-22");
+        if let Some(user_agent) = &self.user_agent {
+            default_request = default_request.header("User-Agent", user_agent);
+        }
         let request = f(default_request);
 
+        #[cfg(test)]
+        if let Some(mock) = &self.mock {
+            let (status, response_body) = mock.respond(request)?;
+            return check_response(status, response_body);
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait().await;
+        }
+
         let response = request
             .send()
             .await
@@ -85,112 +861,428 @@ impl Notion {
             .into_diagnostic()
             .wrap_err("Failed to read Notion API response")?;
 
-        if !status.is_success() {
-            return Err(miette!("Error {}:\n{:#?}", status, response_body));
+        check_response(status, response_body)
+    }
+}
+
+/// Spaces out requests evenly, so that no more than the configured number of requests are started
+/// per second, no matter how many are waiting concurrently.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second,
+            next_slot: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request may be started.
+    async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+fn check_response(status: StatusCode, response_body: Value) -> Result<Value> {
+    if !status.is_success() {
+        return Err(ApiError {
+            status,
+            body: response_body,
         }
+        .into());
+    }
+
+    Ok(response_body)
+}
+
+#[cfg(test)]
+type MockHandler = dyn Fn(&Method, &str, Option<&Value>) -> (StatusCode, Value) + Send + Sync;
+
+/// Answers requests made through a mock `Notion` and records them.
+#[cfg(test)]
+struct MockApi {
+    handler: Box<MockHandler>,
+    calls: std::sync::Mutex<Vec<(Method, String, Option<Value>)>>,
+}
+
+#[cfg(test)]
+impl std::fmt::Debug for MockApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockApi").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+impl MockApi {
+    fn respond(&self, request: RequestBuilder) -> Result<(StatusCode, Value)> {
+        let request = request.build().into_diagnostic()?;
+        let endpoint = request.url().path().trim_start_matches("/v1").to_string();
+        let endpoint = match request.url().query() {
+            Some(query) => format!("{endpoint}?{query}"),
+            None => endpoint,
+        };
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(serde_json::from_slice::<Value>)
+            .transpose()
+            .into_diagnostic()?;
+
+        let response = (self.handler)(request.method(), &endpoint, body.as_ref());
+        self.calls
+            .lock()
+            .unwrap()
+            .push((request.method().clone(), endpoint, body));
+        Ok(response)
+    }
 
-        Ok(response_body)
+    /// The method and endpoint of each request made so far.
+    fn calls(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, endpoint, _)| format!("{method} {endpoint}"))
+            .collect()
     }
 }
 
 impl<'notion> Database<'notion> {
-    async fn get(notion: &'notion Notion, database_id: String) -> Result<Database<'notion>> {
-        // This is async so that we could potentially grab some metadata about the database and its
-        // schema, or just check if it exists, or similar.
-        // We don't currently do any of those though.
+    async fn get(
+        notion: &'notion Notion,
+        database_id: String,
+        mut options: DatabaseOptions,
+    ) -> Result<Database<'notion>> {
+        // Grab the schema, so we can give helpful errors for missing properties before trying to
+        // write an entry.
+        let response = notion
+            .request(Method::GET, &format!("/databases/{}", database_id), |req| {
+                req
+            })
+            .await
+            .map_err(explain_access_error)
+            .wrap_err_with(|| format!("Failed to retrieve database {database_id}"))?;
+        let schema = response["properties"].as_object().map(|properties| {
+            properties
+                .iter()
+                .filter_map(|(name, property)| {
+                    Some((name.clone(), property["type"].as_str()?.to_string()))
+                })
+                .collect()
+        });
 
-        //let response = notion
-        //    .request(Method::GET, &format!("/databases/{}", database_id), |req| {
-        //        req
-        //    })
-        //    .await?;
+        let type_options = response["properties"]["Type"]["select"]["options"]
+            .as_array()
+            .map(|type_options| {
+                type_options
+                    .iter()
+                    .filter_map(|option| option["name"].as_str())
+                    .collect::<Vec<_>>()
+            });
+        if let Some(type_options) = type_options {
+            options.book_type = Some(pick_type_option(options.book_type(), &type_options)?);
+        }
 
         Ok(Self {
             notion,
             database_id,
+            options,
+            schema,
         })
     }
 
-    pub async fn search(&self, title: &str) -> Result<Vec<NotionBookEntry>> {
-        let body = json!({
-            "filter": {
-                "and": [{
-                    "property": "title",
-                    "title": {
-                        "contains": title
-                    }
-                }]
-            }
-        });
-
-        let response = self
-            .notion
+    /// Check that the database exists and is accessible with the configured integration token.
+    pub async fn check(&self) -> Result<()> {
+        self.notion
             .request(
-                Method::POST,
-                &format!("/databases/{}/query", self.database_id),
-                |req| req.json(&body),
+                Method::GET,
+                &format!("/databases/{}", self.database_id),
+                |req| req,
             )
-            .await?;
-
-        let response = response["results"]
-            .as_array()
-            .ok_or_else(|| miette!("No results array in Notion API response!"))?;
+            .await
+            .map_err(explain_access_error)
+            .wrap_err("Notion API check failed")?;
+        Ok(())
+    }
 
-        let results: Vec<NotionBookEntry> = response
-            .into_iter()
-            .map(|res| res.try_into())
-            .try_collect()?;
+    pub async fn search(&self, title: &str) -> Result<Vec<NotionBookEntry>> {
+        let results = self.query(&Filter::title_contains(title)).await?;
 
-        let results =
-            future::try_join_all(results.into_iter().map(|entry| self.get_description(entry)))
-                .await
-                .wrap_err("Failed to get description for page!")?;
+        // Only fetch a few descriptions at once, to stay clear of Notion's rate limit when there
+        // are many matches. `buffered` keeps the results in the order Notion returned them.
+        let concurrency = self
+            .options
+            .description_concurrency
+            .unwrap_or(DEFAULT_DESCRIPTION_CONCURRENCY)
+            .max(1);
+        let results = stream::iter(results)
+            .map(|entry| self.get_description(entry))
+            .buffered(concurrency)
+            .try_collect::<Vec<_>>()
+            .await
+            .wrap_err("Failed to get description for page!")?;
 
         Ok(results)
     }
 
-    async fn get_description(&self, mut entry: NotionBookEntry) -> Result<NotionBookEntry> {
-        let id = entry
-            .id
-            .as_ref()
-            .ok_or_else(|| miette!("Tried to retrieve description for entry without ID!"))?;
-        let response = self
-            .notion
-            .request(Method::GET, &format!("/blocks/{}/children", id), |req| req)
-            .await?;
+    /// Get all entries matching `filter`, going through all pages of results.
+    ///
+    /// Note that this does not check whether the entries have a description.
+    pub async fn query(&self, filter: &Filter) -> Result<Vec<NotionBookEntry>> {
+        self.query_pages(Some(filter)).await
+    }
 
-        let results = response["results"]
+    /// Get all entries in the database.
+    pub async fn all_entries(&self) -> Result<Vec<NotionBookEntry>> {
+        self.query_pages(None).await
+    }
+
+    async fn query_pages(&self, filter: Option<&Filter>) -> Result<Vec<NotionBookEntry>> {
+        let mut body = json!({});
+        if let Some(filter) = filter {
+            body["filter"] = filter.to_json();
+        }
+
+        let pages = self
+            .notion
+            .query_all(&format!("/databases/{}/query", self.database_id), body)
+            .await?;
+
+        let results = pages
+            .iter()
+            .map(|page| NotionBookEntry::from_page(page, &self.options))
+            .collect::<Result<Vec<_>>>()?;
+
+        let results = if self.options.authors_database_id.is_some() {
+            future::try_join_all(
+                results
+                    .into_iter()
+                    .map(|entry| self.resolve_author_names(entry)),
+            )
+            .await
+            .wrap_err("Failed to get authors for page!")?
+        } else {
+            results
+        };
+
+        Ok(results)
+    }
+
+    /// For authors stored as a relation, we only get the IDs of the related pages and have to look
+    /// up their names separately.
+    async fn resolve_author_names(&self, mut entry: NotionBookEntry) -> Result<NotionBookEntry> {
+        let names = future::try_join_all(entry.author_ids.iter().flatten().map(|id| async move {
+            let page = self
+                .notion
+                .request(Method::GET, &format!("/pages/{}", id), |req| req)
+                .await?;
+            page_title(&page).ok_or_else(|| miette!("Author page {} has no title!", id))
+        }))
+        .await?;
+
+        entry.authors = names;
+        Ok(entry)
+    }
+
+    /// Fill in the IDs of author pages in the authors database, creating pages for authors that
+    /// don't have one yet.
+    async fn resolve_author_ids(&self, entry: &mut NotionBookEntry) -> Result<()> {
+        let authors_database_id = match &self.options.authors_database_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        for (name, id) in entry.authors.iter().zip(entry.author_ids.iter_mut()) {
+            if id.is_none() {
+                *id = Some(
+                    self.find_or_create_author(authors_database_id, name)
+                        .await
+                        .wrap_err_with(|| format!("Failed to find or create author {}", name))?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn find_or_create_author(&self, authors_database_id: &str, name: &str) -> Result<String> {
+        let body = json!({
+            "filter": {
+                "property": "title",
+                "title": { "equals": name }
+            }
+        });
+
+        let response = self
+            .notion
+            .request(
+                Method::POST,
+                &format!("/databases/{}/query", authors_database_id),
+                |req| req.json(&body),
+            )
+            .await?;
+
+        if let Some(existing) = response["results"].as_array().and_then(|r| r.first()) {
+            return existing["id"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| miette!("Author page has no ID!"));
+        }
+
+        let body = json!({
+            "parent": { "database_id": authors_database_id },
+            "properties": {
+                "title": {
+                    "title": [{
+                        "text": { "content": name }
+                    }]
+                }
+            }
+        });
+
+        let response = self
+            .notion
+            .request(Method::POST, "/pages/", |req| req.json(&body))
+            .await?;
+
+        response["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| miette!("Created author page has no ID!"))
+    }
+
+    async fn get_description(&self, mut entry: NotionBookEntry) -> Result<NotionBookEntry> {
+        let id = entry
+            .id
+            .as_ref()
+            .ok_or_else(|| miette!("Tried to retrieve description for entry without ID!"))?;
+        let response = self
+            .notion
+            .request(Method::GET, &format!("/blocks/{}/children", id), |req| req)
+            .await?;
+
+        let results = response["results"]
             .as_array()
             .ok_or_else(|| miette!("Get blocks API response has no results!"))?;
 
-        if let Some(_) = results.first() {
+        if !results.is_empty() {
             entry.had_original_description = true;
         }
 
         Ok(entry)
     }
 
-    async fn set_description(&self, id: String, description: &RichText) -> Result<()> {
-        let body = json!({ "children": [rich_text_to_block(description)] });
+    /// Delete all blocks in the body of the page with the given ID.
+    async fn clear_page_body(&self, id: &str) -> Result<()> {
+        let mut block_ids = Vec::new();
+        let mut start_cursor: Option<String> = None;
 
-        self.notion
-            .request(Method::PATCH, &format!("/blocks/{}/children", id), |req| {
-                req.json(&body)
-            })
-            .await?;
+        loop {
+            let endpoint = match &start_cursor {
+                Some(cursor) => format!("/blocks/{}/children?start_cursor={}", id, cursor),
+                None => format!("/blocks/{}/children", id),
+            };
+            let response = self
+                .notion
+                .request(Method::GET, &endpoint, |req| req)
+                .await?;
+
+            let results = response["results"]
+                .as_array()
+                .ok_or_else(|| miette!("Get blocks API response has no results!"))?;
+            for block in results {
+                block_ids.push(
+                    block["id"]
+                        .as_str()
+                        .ok_or_else(|| miette!("Block has no ID!"))?
+                        .to_string(),
+                );
+            }
+
+            match response["next_cursor"].as_str() {
+                Some(cursor) if response["has_more"] == true => {
+                    start_cursor = Some(cursor.to_string())
+                }
+                _ => break,
+            }
+        }
+
+        for block_id in block_ids {
+            self.notion
+                .request(Method::DELETE, &format!("/blocks/{}", block_id), |req| req)
+                .await
+                .wrap_err_with(|| format!("Failed to delete block {}", block_id))?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_description(&self, id: &str, description: &[Block]) -> Result<()> {
+        let id = if self.options.description_child_page {
+            self.create_description_page(id).await?
+        } else {
+            id.to_string()
+        };
+
+        for blocks in description.chunks(MAX_APPENDED_BLOCKS) {
+            let body = json!({ "children": blocks.iter().map(block_to_json).collect::<Vec<_>>() });
+            self.notion
+                .request(Method::PATCH, &format!("/blocks/{}/children", id), |req| {
+                    req.json(&body)
+                })
+                .await?;
+        }
 
         Ok(())
     }
 
-    pub async fn add_entry(&self, book: NotionBookEntry) -> Result<()> {
+    /// Create an empty child page of page `id` to hold its description, returning its ID.
+    async fn create_description_page(&self, id: &str) -> Result<String> {
+        let body = json!({
+            "parent": { "page_id": id },
+            "properties": {
+                "title": { "title": [{ "text": { "content": "Description" } }] }
+            }
+        });
+        let response = self
+            .notion
+            .request(Method::POST, "/pages/", |req| req.json(&body))
+            .await
+            .wrap_err("Failed to create description page!")?;
+
+        response["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| miette!("Created description page has no ID!"))
+    }
+
+    pub async fn add_entry(&self, mut book: NotionBookEntry) -> Result<()> {
+        self.resolve_author_ids(&mut book).await?;
+
         let description = book.description.clone();
         let cover_url = book.cover_url.clone();
 
+        let mut properties = properties_from_entry(book, &self.options);
+        // Who added an entry doesn't change when it's updated, so this is only written here.
+        if let Some(added_by) = &self.options.added_by {
+            properties[self.options.properties.added_by()] = added_by_value(added_by);
+        }
+        self.check_properties(&properties)?;
         let mut body = json!({
             "parent": {
                 "database_id": self.database_id
             },
-            "properties": properties_from_entry(book)
+            "properties": properties
         });
 
         if let Some(url) = cover_url {
@@ -205,31 +1297,48 @@ impl<'notion> Database<'notion> {
             .await?;
 
         if let Some(description) = description {
-            let added_entry =
-                NotionBookEntry::try_from(&response).wrap_err("Failed to parse added page")?;
-            self.set_description(added_entry.id.unwrap(), &description)
-                .await
-                .wrap_err("Failed to set description for new entry!")?;
+            let added_entry = NotionBookEntry::from_page(&response, &self.options)
+                .wrap_err("Failed to parse added page")?;
+            let id = added_entry.id.unwrap();
+            // The page exists at this point, so giving up here means re-running would create a
+            // duplicate. Retry a few times, and otherwise tell the user which page to fix up.
+            retry_transient(DESCRIPTION_ATTEMPTS, RETRY_BASE_DELAY, || {
+                self.set_description(&id, &description)
+            })
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Created page {id}, but failed to set its description! \
+                     Add the description manually rather than re-running, to avoid a duplicate."
+                )
+            })?;
         }
 
         Ok(())
     }
 
-    pub async fn update_entry(&self, book: NotionBookEntry) -> Result<()> {
+    /// Update an existing entry. If `replace_description` is set, an existing page body is
+    /// deleted and replaced by the entry's description, if it has one. Otherwise, the description
+    /// is only set if the page doesn't have a body yet.
+    pub async fn update_entry(
+        &self,
+        mut book: NotionBookEntry,
+        replace_description: bool,
+    ) -> Result<()> {
+        self.resolve_author_ids(&mut book).await?;
+
         let id = book
             .id
             .clone()
             .ok_or_else(|| miette!("Tried to update entry but don't know ID"))?;
 
-        let description_to_set = if book.had_original_description {
-            None
-        } else {
-            book.description.clone()
-        };
-
+        let had_original_description = book.had_original_description;
+        let description = book.description.clone();
         let cover_url = book.cover_url.clone();
 
-        let mut body = json!({ "properties": properties_from_entry(book) });
+        let properties = properties_from_entry(book, &self.options);
+        self.check_properties(&properties)?;
+        let mut body = json!({ "properties": properties });
 
         if let Some(url) = cover_url {
             body.as_object_mut()
@@ -243,66 +1352,571 @@ impl<'notion> Database<'notion> {
             })
             .await?;
 
-        if let Some(description) = description_to_set {
-            self.set_description(id, &description)
+        self.write_description(
+            &id,
+            had_original_description,
+            description,
+            replace_description,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Update only the description of an existing entry, leaving its properties alone. As with
+    /// `update_entry`, an existing page body is only replaced if `replace_description` is set.
+    /// Returns whether the description was written.
+    pub async fn update_description(
+        &self,
+        entry: NotionBookEntry,
+        replace_description: bool,
+    ) -> Result<bool> {
+        let entry = self.get_description(entry).await?;
+        let id = entry
+            .id
+            .as_deref()
+            .ok_or_else(|| miette!("Tried to update description but don't know ID"))?;
+        self.write_description(
+            id,
+            entry.had_original_description,
+            entry.description,
+            replace_description,
+        )
+        .await
+    }
+
+    /// Set `description` as the body of page `id`, unless the page already has a body and
+    /// `replace_description` isn't set. Returns whether the description was written.
+    async fn write_description(
+        &self,
+        id: &str,
+        had_original_description: bool,
+        description: Option<Vec<Block>>,
+        replace_description: bool,
+    ) -> Result<bool> {
+        let description = match description {
+            Some(description) if !had_original_description || replace_description => description,
+            _ => return Ok(false),
+        };
+
+        if had_original_description {
+            self.clear_page_body(id)
                 .await
-                .wrap_err("Failed to set description!")?;
+                .wrap_err("Failed to delete old description!")?;
+        }
+        self.set_description(id, &description)
+            .await
+            .wrap_err("Failed to set description!")?;
+
+        Ok(true)
+    }
+
+    /// Make sure all `properties` we're about to write exist in the database with the right type,
+    /// to give a more helpful error than Notion would.
+    fn check_properties(&self, properties: &Value) -> Result<()> {
+        let schema = match &self.schema {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+
+        let problems = properties
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                // Each property value is an object with the type as its only key.
+                let ty = value.as_object()?.keys().next()?;
+                match schema.get(name) {
+                    None => Some(format!(
+                        "property '{name}' ({ty}) is missing; create it in your database"
+                    )),
+                    Some(actual) if actual != ty => Some(format!(
+                        "property '{name}' has type {actual}, but needs to be {ty}; change its \
+                         type in your database"
+                    )),
+                    Some(_) => None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(miette!(
+                "The database doesn't match what we need to write:\n{}",
+                problems.join("\n")
+            ))
+        }
+    }
+
+    /// Find groups of entries that share an ISBN.
+    pub async fn find_duplicates(&self) -> Result<Vec<Vec<NotionBookEntry>>> {
+        let entries = self.query(&Filter::has_isbn()).await?;
+        Ok(group_by_isbn(entries))
+    }
+
+    /// Archive (i.e. move to the trash) the page with the given ID.
+    pub async fn archive_entry(&self, id: &str) -> Result<()> {
+        self.notion
+            .request(Method::PATCH, &format!("/pages/{}", id), |req| {
+                req.json(&json!({ "archived": true }))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Set the cover of the page with the given ID to an external URL, leaving everything else
+    /// alone.
+    pub async fn update_cover(&self, id: &str, url: &str) -> Result<()> {
+        self.notion
+            .request(Method::PATCH, &format!("/pages/{}", id), |req| {
+                req.json(&json!({ "cover": { "external": { "url": url } } }))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Set the ISBN of page `id`, leaving everything else alone.
+    pub async fn update_isbn(&self, id: &str, isbn: &str) -> Result<()> {
+        self.notion
+            .request(Method::PATCH, &format!("/pages/{}", id), |req| {
+                req.json(&json!({
+                    "properties": { "ISBN": { "rich_text": [{ "text": { "content": isbn } }] } }
+                }))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// The template for the Name of new entries, if one is configured.
+    pub fn title_template(&self) -> Option<&TitleTemplate> {
+        self.options.title_template.as_ref()
+    }
+
+    /// How ownership of a book is stored in the database.
+    pub fn ownership(&self) -> &OwnershipConfig {
+        &self.options.ownership
+    }
+
+    /// Make sure the ownership of entries can be set to the select option `value`, or to owned if
+    /// there is none. A checkbox can only be checked.
+    pub fn check_ownership_value(&self, value: Option<&str>) -> Result<()> {
+        self.ownership_value(value).map(|_| ())
+    }
+
+    fn ownership_value(&self, value: Option<&str>) -> Result<Value> {
+        let ownership = &self.options.ownership;
+        match (ownership.is_checkbox(), value) {
+            (true, None) => Ok(json!({ "checkbox": true })),
+            (true, Some(value)) => Err(miette!(
+                "Can't set ownership to \"{value}\": '{}' is a checkbox",
+                ownership.property()
+            )),
+            (false, value) => Ok(json!({
+                "select": { "name": select_option_name(value.unwrap_or(ownership.owned())) }
+            })),
         }
+    }
+
+    /// Set the ownership of page `id` to owned, or to the select option `value` if given, leaving
+    /// everything else alone.
+    pub async fn set_ownership(&self, id: &str, value: Option<&str>) -> Result<()> {
+        let property = self.ownership_value(value)?;
+        let name = self.options.ownership.property();
+        self.notion
+            .request(Method::PATCH, &format!("/pages/{}", id), |req| {
+                req.json(&json!({ "properties": { name: property } }))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// A filter for entries with an ISBN that weren't synced since `date` (YYYY-MM-DD), or never.
+    pub fn not_synced_since(&self, date: &str) -> Result<Filter> {
+        let name = self.options.properties.last_synced().ok_or_else(|| {
+            miette!("--since needs a `last-synced` property to be configured in the config file")
+        })?;
+        Ok(Filter::And(vec![
+            Filter::has_isbn(),
+            Filter::date_before_or_empty(name, date),
+        ]))
+    }
 
+    /// Set the Last Synced property of page `id` to today, if that property is configured.
+    pub async fn mark_synced(&self, id: &str) -> Result<()> {
+        let name = match self.options.properties.last_synced() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.notion
+            .request(Method::PATCH, &format!("/pages/{}", id), |req| {
+                req.json(&json!({ "properties": { name: { "date": { "start": today() } } } }))
+            })
+            .await?;
         Ok(())
     }
 }
 
-impl TryFrom<&Value> for NotionBookEntry {
-    type Error = miette::Error;
+/// Today's date (in UTC) as YYYY-MM-DD.
+pub fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    date_from_days((seconds / (24 * 60 * 60)) as i64)
+}
+
+/// The date `days` days after 1970-01-01 as YYYY-MM-DD, following
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn date_from_days(days: i64) -> String {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that the leap day is at the end.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Find `book_type` among the options of the database's Type select, ignoring case. Writing a value
+/// that isn't an option yet either fails or silently adds the option, neither of which is wanted.
+fn pick_type_option(book_type: &str, type_options: &[&str]) -> Result<String> {
+    type_options
+        .iter()
+        .find(|option| option.eq_ignore_ascii_case(book_type))
+        .map(|option| option.to_string())
+        .ok_or_else(|| {
+            miette!(
+                "The database's Type property has no option \"{book_type}\" (it has: {}). Add \
+                 the option in Notion, or set `book-type` in the config file to one of the \
+                 existing options.",
+                type_options.join(", ")
+            )
+        })
+}
+
+/// Group entries by ISBN (ignoring dashes and spaces), keeping only groups with more than one entry.
+fn group_by_isbn(entries: Vec<NotionBookEntry>) -> Vec<Vec<NotionBookEntry>> {
+    let mut groups: Vec<(String, Vec<NotionBookEntry>)> = Vec::new();
+    for entry in entries {
+        let isbn = match &entry.isbn {
+            Some(isbn) => isbn.replace(|c: char| c == '-' || c.is_whitespace(), ""),
+            None => continue,
+        };
+        match groups.iter_mut().find(|(key, _)| *key == isbn) {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((isbn, vec![entry])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+const DESCRIPTION_ATTEMPTS: usize = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Run `f` up to `attempts` times, retrying (with exponential backoff starting at `base_delay`) as
+/// long as it fails with a transient Notion API error.
+async fn retry_transient<F, Fut, T>(attempts: usize, base_delay: Duration, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = base_delay;
+    for _ in 1..attempts {
+        match f().await {
+            Err(e)
+                if e.downcast_ref::<ApiError>()
+                    .is_some_and(ApiError::is_transient) =>
+            {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    f().await
+}
+
+/// Concatenate the text of all segments of a rich text value. Text with mixed formatting is split
+/// into several segments.
+fn plain_text(segments: &[Value]) -> String {
+    segments
+        .iter()
+        .filter_map(|segment| segment["plain_text"].as_str())
+        .collect()
+}
+
+/// Extract a database or page ID from either the ID itself (with or without dashes) or a Notion URL
+/// pointing to it, like `https://www.notion.so/workspace/Books-<id>?v=<view id>`.
+pub fn parse_notion_id(id_or_url: &str) -> Result<String> {
+    let without_query = id_or_url
+        .trim()
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default();
+    let last_segment = without_query
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .replace('-', "");
+
+    // For URLs, the ID is prefixed by a slug of the title.
+    let id = last_segment
+        .char_indices()
+        .rev()
+        .nth(31)
+        .map(|(start, _)| &last_segment[start..])
+        .filter(|id| id.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| miette!("Could not find a Notion ID in \"{}\"", id_or_url))?;
+
+    Ok(id.to_lowercase())
+}
+
+/// Notion rejects select and multi-select options with longer names.
+const MAX_OPTION_NAME_CHARS: usize = 100;
+
+impl NotionBookEntry {
+    /// Truncate values that Notion would reject for being too long, instead of having the whole
+    /// request fail. Returns a warning for each truncated value.
+    pub fn enforce_limits(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let mut truncate = |value: &mut String, what: &str| {
+            if let Some((end, _)) = value.char_indices().nth(MAX_OPTION_NAME_CHARS) {
+                warnings.push(format!(
+                    "{what} \"{value}\" is longer than {MAX_OPTION_NAME_CHARS} characters, \
+                     truncating it"
+                ));
+                value.truncate(end);
+            }
+        };
+
+        for author in &mut self.authors {
+            truncate(author, "Author");
+        }
+        if let Some(publisher) = &mut self.publisher {
+            truncate(publisher, "Publisher");
+        }
+
+        warnings
+    }
+
+    /// Merge duplicate entries into one. The most complete entry is kept, and any fields it is
+    /// missing are filled in from the others. Notes are combined, and the result is owned if any of
+    /// the entries is. Page bodies are not merged.
+    pub fn merge(mut entries: Vec<Self>) -> Self {
+        fn completeness(entry: &NotionBookEntry) -> usize {
+            [
+                !entry.authors.is_empty(),
+                entry.publisher.is_some(),
+                entry.published_date.is_some(),
+                entry.isbn.is_some(),
+                entry.cover_url.is_some(),
+                entry.notes.is_some(),
+                entry.edition.is_some(),
+                entry.format.is_some(),
+                entry.dimensions.is_some(),
+                entry.series_position.is_some(),
+            ]
+            .into_iter()
+            .filter(|present| *present)
+            .count()
+        }
+
+        // `max_by_key` returns the last of equally complete entries, so search in reverse to
+        // prefer the first one.
+        let best = entries
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, entry)| completeness(entry))
+            .map_or(0, |(i, _)| i);
+        let mut merged = entries.remove(best);
+
+        for other in entries {
+            merged.owned |= other.owned;
+            if merged.authors.is_empty() {
+                merged.authors = other.authors;
+                merged.author_ids = other.author_ids;
+            }
+            if merged.publisher.is_none() {
+                merged.publisher = other.publisher;
+                merged.publisher_id = other.publisher_id;
+            }
+            merged.published_date = merged.published_date.or(other.published_date);
+            merged.isbn = merged.isbn.or(other.isbn);
+            if merged.isbns.is_empty() {
+                merged.isbns = other.isbns;
+            }
+            if !merged.cover_uploaded {
+                merged.cover_url = merged.cover_url.or(other.cover_url);
+            }
+            merged.edition = merged.edition.or(other.edition);
+            merged.original_year = merged.original_year.or(other.original_year);
+            merged.format = merged.format.or(other.format);
+            merged.source = merged.source.or(other.source);
+            merged.reading_status = merged.reading_status.or(other.reading_status);
+            merged.epub_available = merged.epub_available.or(other.epub_available);
+            merged.pdf_available = merged.pdf_available.or(other.pdf_available);
+            merged.last_synced = merged.last_synced.max(other.last_synced);
+            merged.volume_id = merged.volume_id.or(other.volume_id);
+            merged.self_link = merged.self_link.or(other.self_link);
+            merged.dimensions = merged.dimensions.or(other.dimensions);
+            merged.series_position = merged.series_position.or(other.series_position);
+            if merged.categories.is_empty() {
+                merged.categories = other.categories;
+            }
+            merged.notes = match (merged.notes.take(), other.notes) {
+                (Some(notes), Some(other)) if notes.contains(&other) => Some(notes),
+                (Some(notes), Some(other)) => Some(format!("{notes}\n{other}")),
+                (notes, other) => notes.or(other),
+            };
+        }
 
-    fn try_from(value: &Value) -> Result<Self> {
+        merged
+    }
+
+    fn from_page(value: &Value, options: &DatabaseOptions) -> Result<Self> {
         (|| -> Option<Self> {
             let props = &value["properties"];
+            let properties = &options.properties;
 
-            let authors = props["Authors"]["multi_select"]
-                .as_array()?
-                .iter()
-                .map(|author| Some(author["name"].as_str()?.to_string()))
-                .try_collect()?;
-            let author_ids = props["Authors"]["multi_select"]
-                .as_array()?
-                .iter()
-                .map(|author| Some(Some(author["id"].as_str()?.to_string())))
-                .try_collect()?;
+            let (authors, author_ids) = if options.authors_database_id.is_some() {
+                // Names are filled in later, see `Database::resolve_author_names`.
+                let author_ids: Vec<_> = props["Authors"]["relation"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|author| Some(Some(author["id"].as_str()?.to_string())))
+                    .try_collect()?;
+                (Vec::new(), author_ids)
+            } else {
+                let options = props["Authors"]["multi_select"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let authors = options
+                    .iter()
+                    .map(|author| Some(author["name"].as_str()?.trim().to_string()))
+                    .try_collect()?;
+                let author_ids = options
+                    .iter()
+                    .map(|author| Some(Some(author["id"].as_str()?.to_string())))
+                    .try_collect()?;
+                (authors, author_ids)
+            };
 
-            let owned = props["Ownership"]["select"]
-                .as_object()
-                .map(|s| s["name"].as_str().unwrap() == "Own")
-                .unwrap_or(false);
+            // The concatenated text of a rich text property, if it isn't empty.
+            let rich_text = |name: &str| {
+                props[name]["rich_text"]
+                    .as_array()
+                    .map(|segments| plain_text(segments))
+                    .filter(|text| !text.is_empty())
+            };
+
+            let trimmed = |text: String| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+
+            let ownership = &props[options.ownership.property()];
+            let owned = if options.ownership.is_checkbox() {
+                ownership["checkbox"].as_bool().unwrap_or(false)
+            } else {
+                ownership["select"]
+                    .as_object()
+                    .map(|s| s["name"].as_str().unwrap() == options.ownership.owned())
+                    .unwrap_or(false)
+            };
 
             Some(Self {
                 id: Some(value["id"].as_str()?.to_string()),
-                cover_url: value
-                    .get("cover")
-                    .filter(|c| c.is_object())
-                    .map(|c| c["external"]["url"].as_str().unwrap().to_string()),
-                title: props["Name"]["title"].as_array()?[0]["plain_text"]
-                    .as_str()?
+                cover_url: value["cover"]["external"]["url"]
+                    .as_str()
+                    .map(str::to_string),
+                cover_uploaded: value["cover"]["type"].as_str() == Some("file"),
+                title: plain_text(props["Name"]["title"].as_array()?)
+                    .trim()
                     .to_string(),
                 owned,
                 authors,
                 publisher: props["Publisher"]["select"]
                     .as_object()
-                    .map(|obj| obj["name"].as_str().unwrap().to_string()),
-                published_date: props["Publish Date"]["rich_text"]
-                    .as_array()?
-                    .get(0)
-                    .map(|date| date["plain_text"].as_str().unwrap().to_string()),
-                isbn: props["ISBN"]["rich_text"]
-                    .as_array()?
-                    .get(0)
-                    .map(|isbn| isbn["plain_text"].as_str().unwrap().to_string()),
-                //cover_url: None,
+                    .map(|obj| obj["name"].as_str().unwrap().trim().to_string()),
+                published_date: rich_text("Publish Date").and_then(trimmed),
+                isbn: rich_text("ISBN").and_then(trimmed),
+                isbns: properties
+                    .isbns()
+                    .and_then(rich_text)
+                    .map(|text| split_isbns(&text))
+                    .unwrap_or_default(),
                 author_ids,
                 publisher_id: props["Publisher"]["select"]
                     .as_object()
                     .map(|obj| obj["id"].as_str().unwrap().to_string()),
+                notes: rich_text(properties.notes()),
+                edition: properties.edition().and_then(rich_text),
+                original_year: properties
+                    .original_year()
+                    .and_then(|name| props[name]["number"].as_u64())
+                    .and_then(|year| u16::try_from(year).ok()),
+                dimensions: properties.dimensions().and_then(rich_text),
+                series_position: properties
+                    .series_position()
+                    .and_then(|name| props[name]["number"].as_f64()),
+                categories: properties
+                    .categories()
+                    .and_then(|name| props[name]["multi_select"].as_array())
+                    .map(|options| {
+                        options
+                            .iter()
+                            .filter_map(|option| option["name"].as_str())
+                            .map(|name| name.trim().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                epub_available: properties
+                    .epub()
+                    .and_then(|name| props[name]["checkbox"].as_bool()),
+                pdf_available: properties
+                    .pdf()
+                    .and_then(|name| props[name]["checkbox"].as_bool()),
+                last_synced: properties
+                    .last_synced()
+                    .and_then(|name| props[name]["date"]["start"].as_str())
+                    .map(str::to_string),
+                volume_id: properties.volume_id().and_then(rich_text),
+                self_link: properties
+                    .self_link()
+                    .and_then(|name| props[name]["url"].as_str())
+                    .map(str::to_string),
+                source: properties
+                    .source()
+                    .and_then(|name| props[name]["select"]["name"].as_str())
+                    .map(|source| source.trim().to_string()),
+                format: properties
+                    .format()
+                    .and_then(|name| props[name]["select"]["name"].as_str())
+                    .map(|format| format.trim().to_string()),
+                reading_status: options
+                    .reading_status
+                    .as_ref()
+                    .and_then(|config| {
+                        props[config.property()][config.property_type()]["name"].as_str()
+                    })
+                    .map(|status| status.trim().to_string()),
+                batch_tag: None,
+                raw_description: None,
+                created_by: value["created_by"]["id"].as_str().map(str::to_string),
+                last_edited_by: value["last_edited_by"]["id"].as_str().map(str::to_string),
                 description: None,
                 had_original_description: false,
             })
@@ -311,45 +1925,92 @@ impl TryFrom<&Value> for NotionBookEntry {
     }
 }
 
-fn properties_from_entry(entry: NotionBookEntry) -> Value {
+fn page_title(page: &Value) -> Option<String> {
+    page["properties"]
+        .as_object()?
+        .values()
+        .find(|prop| prop["type"] == "title")?["title"]
+        .as_array()?
+        .iter()
+        .map(|segment| segment["plain_text"].as_str())
+        .collect()
+}
+
+fn properties_from_entry(entry: NotionBookEntry, options: &DatabaseOptions) -> Value {
+    let names = &options.properties;
     let mut properties = Map::<String, Value>::new();
 
-    properties.insert("Type".to_string(), json!({ "select": { "name": "Book" } }));
+    properties.insert(
+        "Type".to_string(),
+        json!({ "select": { "name": options.book_type() } }),
+    );
 
     properties.insert(
         "Name".to_string(),
-        json!({
-            "title": [{
-                "text": { "content": entry.title }
-            }]
-        }),
+        json!({ "title": plain_text_chunks(entry.title.trim()) }),
     );
 
-    if entry.owned {
-        properties.insert(
-            "Ownership".to_string(),
-            json!({
-                "select": { "name": "Own" }
-            }),
-        );
+    let ownership_value = if options.ownership.is_checkbox() {
+        Some(json!({ "checkbox": entry.owned }))
+    } else if entry.owned {
+        Some(json!({ "select": { "name": options.ownership.owned() } }))
+    } else {
+        options
+            .ownership
+            .not_owned()
+            .map(|value| json!({ "select": { "name": value } }))
+    };
+    if let Some(value) = ownership_value {
+        properties.insert(options.ownership.property().to_string(), value);
     }
 
-    let authors = entry
-        .authors
-        .into_iter()
-        .zip(entry.author_ids.into_iter())
-        .map(|(name, id)| match id {
-            Some(id) => json!({ "id": id, "name": name }),
-            None => json!({ "name": name }),
-        })
-        .collect::<Vec<_>>();
+    if let Some(name) = names.authors_text() {
+        if !entry.authors.is_empty() {
+            properties.insert(
+                name.to_string(),
+                json!({ "rich_text": plain_text_chunks(&entry.authors.join(", ")) }),
+            );
+        }
+    }
+
+    if let Some(name) = names.isbns() {
+        if !entry.isbns.is_empty() {
+            properties.insert(
+                name.to_string(),
+                json!({ "rich_text": plain_text_chunks(&options.isbn_separator.join(&entry.isbns)) }),
+            );
+        }
+    }
+
+    if options.authors_database_id.is_some() {
+        let authors = entry
+            .author_ids
+            .into_iter()
+            .flatten()
+            .map(|id| json!({ "id": id }))
+            .collect::<Vec<_>>();
+
+        if !authors.is_empty() {
+            properties.insert("Authors".to_string(), json!({ "relation": authors }));
+        }
+    } else {
+        let authors = entry
+            .authors
+            .into_iter()
+            .zip(entry.author_ids)
+            .map(|(name, id)| match id {
+                Some(id) => json!({ "id": id, "name": select_option_name(&name) }),
+                None => json!({ "name": select_option_name(&name) }),
+            })
+            .collect::<Vec<_>>();
 
-    if authors.len() > 0 {
-        properties.insert("Authors".to_string(), json!({ "multi_select": authors }));
+        if !authors.is_empty() {
+            properties.insert("Authors".to_string(), json!({ "multi_select": authors }));
+        }
     }
 
     if let Some(publisher) = entry.publisher {
-        let sanitized_name = publisher.replace(",", "");
+        let sanitized_name = options.publisher.sanitize(&publisher);
         let value = match entry.publisher_id {
             Some(id) => json!({ "id": id, "name": sanitized_name }),
             None => json!({ "name": sanitized_name }),
@@ -365,34 +2026,181 @@ fn properties_from_entry(entry: NotionBookEntry) -> Value {
     if let Some(date) = entry.published_date {
         properties.insert(
             "Publish Date".to_string(),
-            json!({
-                "rich_text": [{
-                    "text": { "content": date }
-                }]
-            }),
+            json!({ "rich_text": plain_text_chunks(date.trim()) }),
         );
     }
 
     if let Some(isbn) = entry.isbn {
         properties.insert(
             "ISBN".to_string(),
-            json!({
-                "rich_text": [{
-                    "text": { "content": isbn }
-                }]
-            }),
+            json!({ "rich_text": plain_text_chunks(isbn.trim()) }),
         );
     }
 
-    Value::Object(properties)
+    if let (Some(name), Some(edition)) = (names.edition(), entry.edition) {
+        properties.insert(
+            name.to_string(),
+            json!({ "rich_text": plain_text_chunks(&edition) }),
+        );
+    }
+
+    if let (Some(name), Some(year)) = (names.original_year(), entry.original_year) {
+        properties.insert(name.to_string(), json!({ "number": year }));
+    }
+
+    if let (Some(name), Some(dimensions)) = (names.dimensions(), entry.dimensions) {
+        properties.insert(
+            name.to_string(),
+            json!({ "rich_text": plain_text_chunks(&dimensions) }),
+        );
+    }
+
+    if let (Some(name), Some(position)) = (names.series_position(), entry.series_position) {
+        properties.insert(name.to_string(), json!({ "number": position }));
+    }
+
+    if let (Some(name), Some(format)) = (names.format(), entry.format) {
+        properties.insert(
+            name.to_string(),
+            json!({ "select": { "name": select_option_name(&format) } }),
+        );
+    }
+
+    if let Some(name) = names.categories() {
+        let genres = options
+            .genres
+            .map(&entry.categories)
+            .iter()
+            .map(|genre| json!({ "name": select_option_name(genre) }))
+            .collect::<Vec<_>>();
+        if !genres.is_empty() {
+            properties.insert(name.to_string(), json!({ "multi_select": genres }));
+        }
+    }
+
+    if let (Some(name), Some(source)) = (names.source(), entry.source) {
+        properties.insert(
+            name.to_string(),
+            json!({ "select": { "name": select_option_name(&source) } }),
+        );
+    }
+
+    if let (Some(config), Some(status)) = (&options.reading_status, entry.reading_status) {
+        properties.insert(
+            config.property().to_string(),
+            json!({ config.property_type(): { "name": select_option_name(&status) } }),
+        );
+    }
+
+    if let (Some(name), Some(available)) = (names.epub(), entry.epub_available) {
+        properties.insert(name.to_string(), json!({ "checkbox": available }));
+    }
+
+    if let (Some(name), Some(available)) = (names.pdf(), entry.pdf_available) {
+        properties.insert(name.to_string(), json!({ "checkbox": available }));
+    }
+
+    if let (Some(name), Some(date)) = (names.last_synced(), entry.last_synced) {
+        properties.insert(name.to_string(), json!({ "date": { "start": date } }));
+    }
+
+    if let (Some(name), Some(id)) = (names.volume_id(), entry.volume_id) {
+        properties.insert(
+            name.to_string(),
+            json!({ "rich_text": plain_text_chunks(&id) }),
+        );
+    }
+
+    if let (Some(name), Some(link)) = (names.self_link(), entry.self_link) {
+        properties.insert(name.to_string(), json!({ "url": link }));
+    }
+
+    if let (Some(name), Some(raw)) = (names.raw_description(), entry.raw_description) {
+        properties.insert(
+            name.to_string(),
+            json!({ "rich_text": plain_text_chunks(&raw) }),
+        );
+    }
+
+    if let Some(tag) = entry.batch_tag {
+        properties.insert(
+            names.batch().to_string(),
+            json!({ "multi_select": [{ "name": select_option_name(&tag) }] }),
+        );
+    }
+
+    if let Some(notes) = entry.notes {
+        properties.insert(
+            names.notes().to_string(),
+            json!({ "rich_text": plain_text_chunks(&notes) }),
+        );
+    }
+
+    Value::Object(properties)
+}
+
+/// The value of the "Added By" property: A person if `added_by` is the ID of a Notion user,
+/// otherwise a select option.
+fn added_by_value(added_by: &str) -> Value {
+    match parse_notion_id(added_by) {
+        Ok(id) => json!({ "people": [{ "id": id }] }),
+        Err(_) => json!({ "select": { "name": select_option_name(added_by) } }),
+    }
+}
+
+/// Notion rejects rich text segments with more content than this.
+const MAX_TEXT_CHARS: usize = 2000;
+
+/// Notion appends at most this many blocks to a page at once.
+const MAX_APPENDED_BLOCKS: usize = 100;
+
+/// The name of a select or multi-select option for `name`. Notion doesn't allow commas in them,
+/// and trims them, so this does the same to store what will be read back.
+fn select_option_name(name: &str) -> String {
+    name.trim().replace(',', "")
+}
+
+/// Rich text for `text`, split into as many segments as needed to stay within the length limit.
+fn plain_text_chunks(text: &str) -> Value {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .chunks(MAX_TEXT_CHARS)
+        .map(|chunk| json!({ "text": { "content": chunk.iter().collect::<String>() } }))
+        .collect()
 }
 
-fn rich_text_to_block(text: &RichText) -> Value {
-    let mut val = Map::<String, Value>::new();
+fn block_to_json(block: &Block) -> Value {
+    let heading = |level: &u8| match level {
+        1 => "heading_1",
+        2 => "heading_2",
+        _ => "heading_3",
+    };
+    let (ty, text, children) = match block {
+        Block::Paragraph(text) => ("paragraph", text, None),
+        Block::Heading(level, text) => (heading(level), text, None),
+        Block::BulletedListItem(text) => ("bulleted_list_item", text, None),
+        Block::NumberedListItem(text) => ("numbered_list_item", text, None),
+        Block::Quote(text) => ("quote", text, None),
+        Block::Toggle(summary, children) => ("toggle", summary, Some(children)),
+        Block::ToggleHeading(level, text, children) => (heading(level), text, Some(children)),
+    };
+
+    let mut content = json!({ "rich_text": rich_text_to_json(text) });
+    if let Some(children) = children {
+        content["children"] = children.iter().map(block_to_json).collect();
+        if let Block::ToggleHeading(..) = block {
+            content["is_toggleable"] = json!(true);
+        }
+    }
 
-    val.insert("object".to_string(), Value::String("block".to_string()));
-    val.insert("type".to_string(), Value::String("paragraph".to_string()));
+    json!({
+        "object": "block",
+        "type": ty,
+        ty: content,
+    })
+}
 
+fn rich_text_to_json(text: &RichText) -> Value {
     let make_rich_text = |frag: &TextFragment| {
         json!({
             "type": "text",
@@ -404,30 +2212,33 @@ fn rich_text_to_block(text: &RichText) -> Value {
         })
     };
 
-    let paragraph = {
-        let mut par = Map::<String, Value>::new();
-
-        par.insert(
-            "rich_text".to_string(),
-            Value::Array(text.fragments.iter().map(make_rich_text).collect()),
-        );
-
-        Value::Object(par)
-    };
-    val.insert("paragraph".to_string(), paragraph);
-
-    Value::Object(val)
+    Value::Array(text.fragments.iter().map(make_rich_text).collect())
 }
 
 impl Display for NotionBookEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_dates(f, DateDisplay::Raw, Locale::default())
+    }
+}
+
+impl DisplayDates for NotionBookEntry {
+    fn fmt_dates(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        dates: DateDisplay,
+        locale: Locale,
+    ) -> std::fmt::Result {
         write!(
             f,
             "{title} by {authors}",
             title = self.title,
             authors = self.authors.join(", "),
         )?;
-        match (&self.publisher, &self.published_date) {
+        let date = self
+            .published_date
+            .as_deref()
+            .map(|date| dates.format(date, locale));
+        match (&self.publisher, date) {
             (Some(publisher), Some(date)) => write!(f, " ({}, {})", publisher, date)?,
             (Some(publisher), None) => write!(f, " ({})", publisher)?,
             (None, Some(date)) => write!(f, " ({})", date)?,
@@ -439,3 +2250,1934 @@ impl Display for NotionBookEntry {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptions::TextStyle;
+    use std::cell::Cell;
+
+    fn api_error(status: StatusCode) -> miette::Error {
+        ApiError {
+            status,
+            body: json!({}),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn transient_description_failure_is_retried() {
+        let calls = Cell::new(0);
+        let result = retry_transient(3, Duration::ZERO, || {
+            calls.set(calls.get() + 1);
+            let call = calls.get();
+            async move {
+                if call == 1 {
+                    Err(api_error(StatusCode::INTERNAL_SERVER_ERROR))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn persistent_description_failure_gives_up() {
+        let calls = Cell::new(0);
+        let result: Result<()> = retry_transient(3, Duration::ZERO, || {
+            calls.set(calls.get() + 1);
+            async { Err(api_error(StatusCode::BAD_GATEWAY)) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn non_transient_description_failure_is_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<()> = retry_transient(3, Duration::ZERO, || {
+            calls.set(calls.get() + 1);
+            async { Err(api_error(StatusCode::BAD_REQUEST)) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn read_entry_fields_are_trimmed() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": " Dune " }] },
+                "Authors": { "multi_select": [{ "id": "a", "name": "Frank Herbert  " }] },
+                "Publisher": { "select": { "id": "p", "name": "  Penguin " } },
+                "Publish Date": { "rich_text": [{ "plain_text": " 1990" }] },
+                "ISBN": { "rich_text": [{ "plain_text": "9780441013593\n" }] },
+                "Ownership": { "select": null },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+
+        assert_eq!(entry.title, "Dune");
+        assert_eq!(entry.authors, ["Frank Herbert"]);
+        assert_eq!(entry.publisher.as_deref(), Some("Penguin"));
+        assert_eq!(entry.published_date.as_deref(), Some("1990"));
+        assert_eq!(entry.isbn.as_deref(), Some("9780441013593"));
+    }
+
+    #[test]
+    fn notes_are_read_from_configured_property() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Remarks": { "rich_text": [
+                    { "plain_text": "Signed " },
+                    { "plain_text": "copy" },
+                ] },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                notes: Some("Remarks".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let entry = NotionBookEntry::from_page(&page, &options).unwrap();
+
+        assert_eq!(entry.notes.as_deref(), Some("Signed copy"));
+    }
+
+    #[test]
+    fn series_position_is_read_and_written() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune Messiah" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Series Position": { "number": 2 },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                series_position: Some("Series Position".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let entry = NotionBookEntry::from_page(&page, &options).unwrap();
+        assert_eq!(entry.series_position, Some(2.0));
+
+        assert_eq!(
+            properties_from_entry(entry, &options)["Series Position"],
+            json!({ "number": 2.0 })
+        );
+    }
+
+    #[test]
+    fn source_is_read_and_written() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Source": { "select": { "id": "s", "name": "Google Books" } },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                source: Some("Source".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let entry = NotionBookEntry::from_page(&page, &options).unwrap();
+        assert_eq!(entry.source.as_deref(), Some("Google Books"));
+
+        assert_eq!(
+            properties_from_entry(entry.clone(), &options)["Source"],
+            json!({ "select": { "name": "Google Books" } })
+        );
+        assert!(properties_from_entry(entry, &DatabaseOptions::default())
+            .get("Source")
+            .is_none());
+    }
+
+    #[test]
+    fn download_availability_is_read_and_written() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "EPUB": { "checkbox": true },
+                "PDF": { "checkbox": false },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                epub: Some("EPUB".to_string()),
+                pdf: Some("PDF".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut entry = NotionBookEntry::from_page(&page, &options).unwrap();
+        assert_eq!(entry.epub_available, Some(true));
+        assert_eq!(entry.pdf_available, Some(false));
+
+        let properties = properties_from_entry(entry.clone(), &options);
+        assert_eq!(properties["EPUB"], json!({ "checkbox": true }));
+        assert_eq!(properties["PDF"], json!({ "checkbox": false }));
+
+        // Unknown availability is left alone.
+        entry.pdf_available = None;
+        assert!(properties_from_entry(entry, &options).get("PDF").is_none());
+    }
+
+    #[test]
+    fn last_synced_is_read_and_written() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Last Synced": { "date": { "start": "2024-01-31", "end": null } },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                last_synced: Some("Last Synced".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let entry = NotionBookEntry::from_page(&page, &options).unwrap();
+        assert_eq!(entry.last_synced.as_deref(), Some("2024-01-31"));
+        assert_eq!(
+            properties_from_entry(entry, &options)["Last Synced"],
+            json!({ "date": { "start": "2024-01-31" } })
+        );
+    }
+
+    #[test]
+    fn volume_id_and_self_link_are_read_and_written() {
+        let link = "https://www.googleapis.com/books/v1/volumes/B1hSG45JCX4C";
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Volume ID": { "rich_text": [{ "plain_text": "B1hSG45JCX4C" }] },
+                "Self Link": { "url": link },
+            }
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                volume_id: Some("Volume ID".to_string()),
+                self_link: Some("Self Link".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let entry = NotionBookEntry::from_page(&page, &options).unwrap();
+        assert_eq!(entry.volume_id.as_deref(), Some("B1hSG45JCX4C"));
+        assert_eq!(entry.self_link.as_deref(), Some(link));
+
+        let properties = properties_from_entry(entry.clone(), &options);
+        assert_eq!(
+            properties["Volume ID"],
+            json!({ "rich_text": [{ "text": { "content": "B1hSG45JCX4C" } }] })
+        );
+        assert_eq!(properties["Self Link"], json!({ "url": link }));
+
+        let properties = properties_from_entry(entry, &DatabaseOptions::default());
+        assert!(properties.get("Volume ID").is_none());
+        assert!(properties.get("Self Link").is_none());
+    }
+
+    fn author_relation_options() -> DatabaseOptions {
+        DatabaseOptions {
+            authors_database_id: Some("authors-db".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn authors_are_written_as_relation() {
+        let entry = NotionBookEntry {
+            authors: vec!["Terry Pratchett".to_string(), "Neil Gaiman".to_string()],
+            author_ids: vec![Some("pratchett".to_string()), Some("gaiman".to_string())],
+            ..NotionBookEntry::new("Good Omens")
+        };
+        let properties = properties_from_entry(entry, &author_relation_options());
+
+        assert_eq!(
+            properties["Authors"],
+            json!({ "relation": [{ "id": "pratchett" }, { "id": "gaiman" }] })
+        );
+    }
+
+    #[test]
+    fn authors_are_also_written_as_text() {
+        let entry = NotionBookEntry {
+            authors: vec!["Terry Pratchett".to_string(), "Neil Gaiman".to_string()],
+            author_ids: vec![None, None],
+            ..NotionBookEntry::new("Good Omens")
+        };
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                authors_text: Some("Authors (text)".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let properties = properties_from_entry(entry.clone(), &options);
+        assert_eq!(
+            properties["Authors (text)"],
+            json!({ "rich_text": [{ "text": { "content": "Terry Pratchett, Neil Gaiman" } }] })
+        );
+        assert_eq!(
+            properties["Authors"],
+            json!({ "multi_select": [{ "name": "Terry Pratchett" }, { "name": "Neil Gaiman" }] })
+        );
+
+        let properties = properties_from_entry(entry, &DatabaseOptions::default());
+        assert!(properties.get("Authors (text)").is_none());
+    }
+
+    fn isbns_options(separator: IsbnSeparator) -> DatabaseOptions {
+        DatabaseOptions {
+            properties: PropertyNames {
+                isbns: Some("ISBNs".to_string()),
+                ..Default::default()
+            },
+            isbn_separator: separator,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn isbns_are_joined_and_split() {
+        let isbns = strings(&["9780441013593", "0441013597"]);
+        assert_eq!(
+            IsbnSeparator::Comma.join(&isbns),
+            "9780441013593, 0441013597"
+        );
+        assert_eq!(
+            IsbnSeparator::Newline.join(&isbns),
+            "9780441013593\n0441013597"
+        );
+        for separator in [IsbnSeparator::Comma, IsbnSeparator::Newline] {
+            assert_eq!(split_isbns(&separator.join(&isbns)), isbns);
+        }
+        assert_eq!(split_isbns(" 9780441013593,\n\n0441013597 "), isbns);
+        assert!(split_isbns("").is_empty());
+
+        assert_eq!(
+            IsbnSeparator::parse("Newline").unwrap(),
+            IsbnSeparator::Newline
+        );
+        assert!(IsbnSeparator::parse("semicolon").is_err());
+    }
+
+    #[test]
+    fn isbns_are_written_to_one_property() {
+        let entry = NotionBookEntry {
+            id: Some("page-id".to_string()),
+            isbn: Some("9780441013593".to_string()),
+            isbns: strings(&["9780441013593", "0441013597"]),
+            ..NotionBookEntry::new("Dune")
+        };
+        let properties =
+            properties_from_entry(entry.clone(), &isbns_options(IsbnSeparator::Newline));
+        assert_eq!(
+            properties["ISBNs"],
+            json!({ "rich_text": [{ "text": { "content": "9780441013593\n0441013597" } }] })
+        );
+
+        for separator in [IsbnSeparator::Comma, IsbnSeparator::Newline] {
+            assert_eq!(round_trip(entry.clone(), &isbns_options(separator)), entry);
+        }
+
+        let properties = properties_from_entry(entry, &DatabaseOptions::default());
+        assert!(properties.get("ISBNs").is_none());
+    }
+
+    #[test]
+    fn authors_are_read_from_relation() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Good Omens" }] },
+                "Authors": { "relation": [{ "id": "pratchett" }, { "id": "gaiman" }] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &author_relation_options()).unwrap();
+
+        assert!(entry.authors.is_empty());
+        assert_eq!(
+            entry.author_ids,
+            [Some("pratchett".to_string()), Some("gaiman".to_string())]
+        );
+    }
+
+    #[test]
+    fn title_of_author_page() {
+        let page = json!({
+            "id": "pratchett",
+            "properties": {
+                "Born": { "type": "date", "date": null },
+                "Name": {
+                    "type": "title",
+                    "title": [{ "plain_text": "Terry " }, { "plain_text": "Pratchett" }]
+                },
+            }
+        });
+        assert_eq!(page_title(&page).as_deref(), Some("Terry Pratchett"));
+    }
+
+    #[test]
+    fn title_filter_json() {
+        assert_eq!(
+            Filter::title_contains("Dune").to_json(),
+            json!({ "property": "title", "title": { "contains": "Dune" } })
+        );
+    }
+
+    #[test]
+    fn missing_isbn_filter_json() {
+        assert_eq!(
+            Filter::missing_isbn().to_json(),
+            json!({ "property": "ISBN", "rich_text": { "is_empty": true } })
+        );
+    }
+
+    #[test]
+    fn date_filter_json() {
+        assert_eq!(
+            Filter::date_before_or_empty("Last Synced", "2024-01-31").to_json(),
+            json!({
+                "or": [
+                    { "property": "Last Synced", "date": { "is_empty": true } },
+                    { "property": "Last Synced", "date": { "before": "2024-01-31" } },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn dates_from_days() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(59), "1970-03-01");
+        assert_eq!(date_from_days(11_016), "2000-02-29");
+        assert_eq!(date_from_days(19_753), "2024-01-31");
+        assert_eq!(date_from_days(-1), "1969-12-31");
+    }
+
+    #[test]
+    fn combined_filter_json() {
+        let filter = Filter::And(vec![
+            Filter::missing_isbn(),
+            Filter::not_owned(&OwnershipConfig::default()),
+        ]);
+        assert_eq!(
+            filter.to_json(),
+            json!({
+                "and": [
+                    { "property": "ISBN", "rich_text": { "is_empty": true } },
+                    {
+                        "or": [
+                            { "property": "Ownership", "select": { "is_empty": true } },
+                            { "property": "Ownership", "select": { "does_not_equal": "Own" } },
+                        ]
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn publisher_sanitization_defaults() {
+        let sanitization = PublisherSanitization::default();
+        assert_eq!(
+            sanitization.sanitize(" Farrar, Straus  and Giroux. "),
+            "Farrar Straus  and Giroux."
+        );
+    }
+
+    #[test]
+    fn publisher_whitespace_and_punctuation() {
+        let sanitization = PublisherSanitization {
+            collapse_whitespace: true,
+            strip_trailing_punctuation: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            sanitization.sanitize("Penguin \t Books  Ltd.,"),
+            "Penguin Books Ltd"
+        );
+    }
+
+    fn genre_mapping() -> GenreMapping {
+        let rule = |category: &str, genre: &str| GenreRule {
+            category: category.to_string(),
+            genre: genre.to_string(),
+        };
+        GenreMapping {
+            exact: vec![rule(
+                "Juvenile Fiction / Fantasy & Magic",
+                "Children's Fantasy",
+            )],
+            contains: vec![
+                rule("fantasy", "Fantasy"),
+                rule("Science Fiction", "Sci-Fi"),
+            ],
+            drop_unmapped: false,
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn categories_are_mapped_to_genres() {
+        let mapping = genre_mapping();
+        assert_eq!(
+            mapping.map(&strings(&[
+                "Juvenile Fiction / Fantasy & Magic",
+                "Fiction / Fantasy / Epic",
+                "Fiction / Science Fiction / General",
+                "FICTION / FANTASY / General",
+            ])),
+            ["Children's Fantasy", "Fantasy", "Sci-Fi"]
+        );
+    }
+
+    #[test]
+    fn unmapped_categories_are_passed_through() {
+        let mapping = genre_mapping();
+        assert_eq!(
+            mapping.map(&strings(&["History / Europe", " Fiction / Fantasy / Epic"])),
+            ["History / Europe", "Fantasy"]
+        );
+        assert_eq!(
+            GenreMapping::default().map(&strings(&["History / Europe"])),
+            ["History / Europe"]
+        );
+    }
+
+    #[test]
+    fn unmapped_categories_can_be_dropped() {
+        let mapping = GenreMapping {
+            drop_unmapped: true,
+            ..genre_mapping()
+        };
+        assert_eq!(
+            mapping.map(&strings(&["History / Europe", "Fiction / Fantasy / Epic"])),
+            ["Fantasy"]
+        );
+        // Genres read back from the database are kept, even if another rule would match them.
+        assert_eq!(
+            mapping.map(&strings(&["Sci-Fi", "Children's Fantasy", "Cooking"])),
+            ["Sci-Fi", "Children's Fantasy"]
+        );
+        assert!(mapping.map(&strings(&["Cooking"])).is_empty());
+    }
+
+    #[test]
+    fn genres_are_written_to_the_categories_property() {
+        let options = DatabaseOptions {
+            genres: genre_mapping(),
+            ..round_trip_options()
+        };
+        let entry = NotionBookEntry {
+            categories: strings(&["Fiction / Fantasy / Epic", "History, Europe"]),
+            ..NotionBookEntry::new("The Name of the Wind")
+        };
+        assert_eq!(
+            properties_from_entry(entry, &options)["Categories"],
+            json!({ "multi_select": [{ "name": "Fantasy" }, { "name": "History Europe" }] })
+        );
+
+        let without_property = DatabaseOptions {
+            genres: genre_mapping(),
+            ..Default::default()
+        };
+        let entry = NotionBookEntry {
+            categories: strings(&["Fiction / Fantasy / Epic"]),
+            ..NotionBookEntry::new("The Name of the Wind")
+        };
+        let properties = properties_from_entry(entry, &without_property);
+        assert!(properties.get("Categories").is_none());
+    }
+
+    #[test]
+    fn publisher_aliases() {
+        let sanitization = PublisherSanitization {
+            collapse_whitespace: true,
+            aliases: vec![PublisherAlias {
+                name: "Penguin Books Ltd".to_string(),
+                replacement: "Penguin".to_string(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(sanitization.sanitize("Penguin  Books Ltd"), "Penguin");
+        assert_eq!(
+            sanitization.sanitize("Penguin Classics"),
+            "Penguin Classics"
+        );
+    }
+
+    fn custom_ownership_options() -> DatabaseOptions {
+        DatabaseOptions {
+            ownership: OwnershipConfig {
+                property: Some("Have".to_string()),
+                owned: Some("Yes".to_string()),
+                not_owned: Some("No".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn page_with_ownership(property: &str, value: &str) -> Value {
+        json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                property: { "select": { "id": "o", "name": value } },
+            }
+        })
+    }
+
+    #[test]
+    fn default_ownership_values() {
+        let options = DatabaseOptions::default();
+        let page = page_with_ownership("Ownership", "Own");
+        assert!(NotionBookEntry::from_page(&page, &options).unwrap().owned);
+        let page = page_with_ownership("Ownership", "Yes");
+        assert!(!NotionBookEntry::from_page(&page, &options).unwrap().owned);
+    }
+
+    #[test]
+    fn custom_ownership_values_are_read() {
+        let options = custom_ownership_options();
+        let page = page_with_ownership("Have", "Yes");
+        assert!(NotionBookEntry::from_page(&page, &options).unwrap().owned);
+        let page = page_with_ownership("Have", "No");
+        assert!(!NotionBookEntry::from_page(&page, &options).unwrap().owned);
+        let page = page_with_ownership("Ownership", "Own");
+        assert!(!NotionBookEntry::from_page(&page, &options).unwrap().owned);
+    }
+
+    #[test]
+    fn custom_ownership_values_are_written() {
+        let options = custom_ownership_options();
+        let page = page_with_ownership("Have", "Yes");
+        let mut entry = NotionBookEntry::from_page(&page, &options).unwrap();
+
+        let properties = properties_from_entry(entry.clone(), &options);
+        assert_eq!(properties["Have"], json!({ "select": { "name": "Yes" } }));
+
+        entry.owned = false;
+        let properties = properties_from_entry(entry, &options);
+        assert_eq!(properties["Have"], json!({ "select": { "name": "No" } }));
+    }
+
+    fn checkbox_ownership_options() -> DatabaseOptions {
+        DatabaseOptions {
+            ownership: OwnershipConfig {
+                property: Some("Owned".to_string()),
+                checkbox: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn page_with_checkbox(checked: bool) -> Value {
+        json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Owned": { "checkbox": checked },
+            }
+        })
+    }
+
+    #[test]
+    fn checkbox_ownership_is_read() {
+        let options = checkbox_ownership_options();
+        let page = page_with_checkbox(true);
+        assert!(NotionBookEntry::from_page(&page, &options).unwrap().owned);
+        let page = page_with_checkbox(false);
+        assert!(!NotionBookEntry::from_page(&page, &options).unwrap().owned);
+    }
+
+    #[test]
+    fn checkbox_ownership_is_written() {
+        let options = checkbox_ownership_options();
+        let page = page_with_checkbox(true);
+        let mut entry = NotionBookEntry::from_page(&page, &options).unwrap();
+
+        let properties = properties_from_entry(entry.clone(), &options);
+        assert_eq!(properties["Owned"], json!({ "checkbox": true }));
+
+        entry.owned = false;
+        let properties = properties_from_entry(entry, &options);
+        assert_eq!(properties["Owned"], json!({ "checkbox": false }));
+    }
+
+    fn reading_status_options(status: bool) -> DatabaseOptions {
+        DatabaseOptions {
+            reading_status: Some(ReadingStatusConfig {
+                property: Some("Progress".to_string()),
+                status,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reading_status_is_written_as_select_or_status() {
+        let entry = NotionBookEntry {
+            reading_status: Some("To Read".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        let properties = properties_from_entry(entry.clone(), &reading_status_options(false));
+        assert_eq!(
+            properties["Progress"],
+            json!({ "select": { "name": "To Read" } })
+        );
+        let properties = properties_from_entry(entry.clone(), &reading_status_options(true));
+        assert_eq!(
+            properties["Progress"],
+            json!({ "status": { "name": "To Read" } })
+        );
+
+        // Without a configured property, the status isn't written at all.
+        let properties = properties_from_entry(entry, &DatabaseOptions::default());
+        assert!(properties.get("Reading Status").is_none());
+    }
+
+    #[test]
+    fn reading_status_round_trips() {
+        let entry = NotionBookEntry {
+            id: Some("page-id".to_string()),
+            reading_status: Some("In Progress".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        for status in [false, true] {
+            let options = reading_status_options(status);
+            assert_eq!(round_trip(entry.clone(), &options), entry);
+        }
+    }
+
+    #[test]
+    fn status_property_is_read() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+                "Progress": {
+                    "id": "prop",
+                    "type": "status",
+                    "status": { "id": "s", "name": "Finished ", "color": "green" }
+                },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &reading_status_options(true)).unwrap();
+        assert_eq!(entry.reading_status.as_deref(), Some("Finished"));
+        // A status isn't mistaken for a select.
+        let entry = NotionBookEntry::from_page(&page, &reading_status_options(false)).unwrap();
+        assert_eq!(entry.reading_status, None);
+    }
+
+    #[test]
+    fn checkbox_not_owned_filter_json() {
+        let options = checkbox_ownership_options();
+        assert_eq!(
+            Filter::not_owned(&options.ownership).to_json(),
+            json!({ "property": "Owned", "checkbox": { "equals": false } })
+        );
+    }
+
+    #[test]
+    fn long_text_is_chunked() {
+        let text = "é".repeat(MAX_TEXT_CHARS) + "<b>rest</b>";
+        let chunks = plain_text_chunks(&text);
+        let contents = chunks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk["text"]["content"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            ["é".repeat(MAX_TEXT_CHARS).as_str(), "<b>rest</b>"]
+        );
+
+        assert_eq!(plain_text_chunks(""), json!([]));
+    }
+
+    #[tokio::test]
+    async fn added_by_is_written_on_create() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let options = DatabaseOptions {
+            added_by: Some("Anna".to_string()),
+            ..Default::default()
+        };
+        let database = notion.database("db".to_string(), options).await.unwrap();
+
+        let entry = NotionBookEntry {
+            isbn: Some("9780441013593".to_string()),
+            ..NotionBookEntry::new("Dune")
+        };
+        database.add_entry(entry).await.unwrap();
+        database
+            .update_entry(page_to_update(), false)
+            .await
+            .unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls()[..3],
+            ["GET /databases/db", "POST /pages/", "PATCH /pages/page-id"]
+        );
+        let calls = mock.calls.lock().unwrap();
+        assert_eq!(
+            calls[1].2.as_ref().unwrap()["properties"]["Added By"],
+            json!({ "select": { "name": "Anna" } })
+        );
+        // Updating an entry leaves it alone.
+        assert!(calls[2].2.as_ref().unwrap()["properties"]
+            .get("Added By")
+            .is_none());
+    }
+
+    #[test]
+    fn added_by_can_be_a_notion_user() {
+        assert_eq!(
+            added_by_value("0c3e5b64-6f9e-4d2b-8a3f-2d4c6b8e1f70"),
+            json!({ "people": [{ "id": "0c3e5b646f9e4d2b8a3f2d4c6b8e1f70" }] })
+        );
+        assert_eq!(
+            added_by_value("Grandpa, Joe"),
+            json!({ "select": { "name": "Grandpa Joe" } })
+        );
+    }
+
+    /// The page Notion returns after `properties` were written to it: Text gets its `plain_text`,
+    /// and new select and multi-select options get an ID.
+    fn page_from_properties(mut properties: Value) -> Value {
+        for value in properties.as_object_mut().unwrap().values_mut() {
+            let (ty, content) = value.as_object_mut().unwrap().iter_mut().next().unwrap();
+            match ty.as_str() {
+                "title" | "rich_text" => {
+                    for segment in content.as_array_mut().unwrap() {
+                        segment["plain_text"] = segment["text"]["content"].clone();
+                    }
+                }
+                "select" | "status" => {
+                    if content["id"].is_null() {
+                        content["id"] = content["name"].clone();
+                    }
+                }
+                "multi_select" => {
+                    for option in content.as_array_mut().unwrap() {
+                        if option["id"].is_null() {
+                            option["id"] = option["name"].clone();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        json!({ "id": "page-id", "properties": properties })
+    }
+
+    fn round_trip(entry: NotionBookEntry, options: &DatabaseOptions) -> NotionBookEntry {
+        let page = page_from_properties(properties_from_entry(entry, options));
+        NotionBookEntry::from_page(&page, options).unwrap()
+    }
+
+    fn round_trip_options() -> DatabaseOptions {
+        DatabaseOptions {
+            properties: PropertyNames {
+                edition: Some("Edition".to_string()),
+                original_year: Some("Original Year".to_string()),
+                format: Some("Format".to_string()),
+                dimensions: Some("Dimensions".to_string()),
+                series_position: Some("Series Position".to_string()),
+                categories: Some("Categories".to_string()),
+                source: Some("Source".to_string()),
+                epub: Some("EPUB".to_string()),
+                pdf: Some("PDF".to_string()),
+                last_synced: Some("Last Synced".to_string()),
+                volume_id: Some("Volume ID".to_string()),
+                self_link: Some("Self Link".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let options = round_trip_options();
+        let entry = NotionBookEntry {
+            id: Some("page-id".to_string()),
+            owned: true,
+            authors: vec!["Terry Pratchett".to_string(), "Neil Gaiman".to_string()],
+            author_ids: vec![Some("pratchett".to_string()), Some("gaiman".to_string())],
+            publisher: Some("Workman".to_string()),
+            publisher_id: Some("workman".to_string()),
+            published_date: Some("1990-05-01".to_string()),
+            isbn: Some("9780060853983".to_string()),
+            notes: Some("Signed copy\nFrom the library sale".to_string()),
+            edition: Some("First Edition".to_string()),
+            original_year: Some(1988),
+            format: Some("Hardcover".to_string()),
+            dimensions: Some("24 x 16 x 3 cm".to_string()),
+            series_position: Some(1.5),
+            categories: vec!["Fantasy".to_string(), "Humor".to_string()],
+            source: Some("Google Books".to_string()),
+            epub_available: Some(true),
+            pdf_available: Some(false),
+            last_synced: Some("2024-01-31".to_string()),
+            volume_id: Some("B1hSG45JCX4C".to_string()),
+            self_link: Some("https://www.googleapis.com/books/v1/volumes/B1hSG45JCX4C".to_string()),
+            ..NotionBookEntry::new("Good Omens")
+        };
+        assert_eq!(round_trip(entry.clone(), &options), entry);
+
+        let not_owned = NotionBookEntry {
+            owned: false,
+            ..entry.clone()
+        };
+        assert_eq!(round_trip(not_owned.clone(), &options), not_owned);
+        let checkbox_options = DatabaseOptions {
+            ownership: OwnershipConfig {
+                checkbox: true,
+                ..Default::default()
+            },
+            ..round_trip_options()
+        };
+        assert_eq!(round_trip(entry.clone(), &checkbox_options), entry);
+        assert_eq!(round_trip(not_owned.clone(), &checkbox_options), not_owned);
+
+        // Text longer than Notion allows in one segment is split up, and joined again when read.
+        let long_notes = NotionBookEntry {
+            notes: Some("Signed copy. ".repeat(MAX_TEXT_CHARS / 5)),
+            ..entry
+        };
+        assert_eq!(round_trip(long_notes.clone(), &options), long_notes);
+    }
+
+    #[test]
+    fn values_are_written_as_they_are_read_back() {
+        let options = round_trip_options();
+        let entry = NotionBookEntry {
+            id: Some("page-id".to_string()),
+            authors: vec!["Martin Luther King, Jr. ".to_string()],
+            author_ids: vec![None],
+            publisher: Some(" Beacon Press, Inc.".to_string()),
+            published_date: Some("1963 ".to_string()),
+            isbn: Some(" 9780807000861".to_string()),
+            format: Some("Hardcover, Illustrated".to_string()),
+            ..NotionBookEntry::new(" Strength to Love\n")
+        };
+        let read = round_trip(entry, &options);
+        assert_eq!(read.title, "Strength to Love");
+        assert_eq!(read.authors, ["Martin Luther King Jr."]);
+        assert_eq!(read.publisher.as_deref(), Some("Beacon Press Inc."));
+        assert_eq!(read.published_date.as_deref(), Some("1963"));
+        assert_eq!(read.isbn.as_deref(), Some("9780807000861"));
+        assert_eq!(read.format.as_deref(), Some("Hardcover Illustrated"));
+        assert_eq!(round_trip(read.clone(), &options), read);
+
+        let properties = properties_from_entry(read, &options);
+        assert_eq!(
+            properties["Authors"],
+            json!({ "multi_select": [
+                { "id": "Martin Luther King Jr.", "name": "Martin Luther King Jr." },
+            ] })
+        );
+
+        // Empty text is cleared instead of written as an empty segment, which reads back as none.
+        let blank_isbn = NotionBookEntry {
+            isbn: Some(" ".to_string()),
+            ..NotionBookEntry::new("Strength to Love")
+        };
+        assert_eq!(
+            properties_from_entry(blank_isbn, &options)["ISBN"],
+            json!({ "rich_text": [] })
+        );
+    }
+
+    #[test]
+    fn block_json() {
+        let heading = Block::Heading(
+            2,
+            RichText {
+                fragments: vec![TextFragment::new("About", TextStyle::bold())],
+            },
+        );
+        assert_eq!(
+            block_to_json(&heading),
+            json!({
+                "object": "block",
+                "type": "heading_2",
+                "heading_2": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "About" },
+                        "annotations": { "bold": true, "italic": false },
+                    }]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn toggle_heading_block_json() {
+        let heading = Block::ToggleHeading(
+            2,
+            RichText {
+                fragments: vec![TextFragment::new("Description", TextStyle::unstyled())],
+            },
+            vec![Block::Paragraph(RichText {
+                fragments: vec![TextFragment::new("Text", TextStyle::italic())],
+            })],
+        );
+        assert_eq!(
+            block_to_json(&heading),
+            json!({
+                "object": "block",
+                "type": "heading_2",
+                "heading_2": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "Description" },
+                        "annotations": { "bold": false, "italic": false },
+                    }],
+                    "is_toggleable": true,
+                    "children": [{
+                        "object": "block",
+                        "type": "paragraph",
+                        "paragraph": {
+                            "rich_text": [{
+                                "type": "text",
+                                "text": { "content": "Text" },
+                                "annotations": { "bold": false, "italic": true },
+                            }]
+                        },
+                    }],
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn toggle_block_json() {
+        let toggle = Block::Toggle(
+            RichText {
+                fragments: vec![TextFragment::new("Description", TextStyle::unstyled())],
+            },
+            vec![Block::Quote(RichText {
+                fragments: vec![TextFragment::new("Text", TextStyle::unstyled())],
+            })],
+        );
+        let text = |content: &str| {
+            json!([{
+                "type": "text",
+                "text": { "content": content },
+                "annotations": { "bold": false, "italic": false },
+            }])
+        };
+        assert_eq!(
+            block_to_json(&toggle),
+            json!({
+                "object": "block",
+                "type": "toggle",
+                "toggle": {
+                    "rich_text": text("Description"),
+                    "children": [{
+                        "object": "block",
+                        "type": "quote",
+                        "quote": { "rich_text": text("Text") },
+                    }],
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn overlong_option_names_are_truncated() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Anthology" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let mut entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        entry.authors = vec!["é".repeat(150), "Short Name".to_string()];
+        entry.publisher = Some("Publisher".to_string());
+
+        let warnings = entry.enforce_limits();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Author"));
+        assert_eq!(entry.authors[0], "é".repeat(100));
+        assert_eq!(entry.authors[1], "Short Name");
+        assert_eq!(entry.publisher.as_deref(), Some("Publisher"));
+    }
+
+    #[test]
+    fn batch_tag_is_written() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let options = DatabaseOptions::default();
+        let mut entry = NotionBookEntry::from_page(&page, &options).unwrap();
+
+        assert!(properties_from_entry(entry.clone(), &options)
+            .get("Batch")
+            .is_none());
+
+        entry.batch_tag = Some("moving-box-3".to_string());
+        assert_eq!(
+            properties_from_entry(entry, &options)["Batch"],
+            json!({ "multi_select": [{ "name": "moving-box-3" }] })
+        );
+    }
+
+    #[test]
+    fn notion_ids_from_urls() {
+        let id = "0123456789abcdef0123456789abcdef";
+        let cases = [
+            "0123456789abcdef0123456789abcdef",
+            "01234567-89ab-cdef-0123-456789abcdef",
+            "https://www.notion.so/0123456789abcdef0123456789abcdef",
+            "https://www.notion.so/workspace/0123456789abcdef0123456789abcdef?v=fedcba9876543210fedcba9876543210",
+            "https://www.notion.so/workspace/My-Books-0123456789ABCDEF0123456789ABCDEF?v=fedcba9876543210fedcba9876543210&pvs=4",
+            "notion.so/workspace/Books-0123456789abcdef0123456789abcdef/",
+        ];
+        for case in cases {
+            assert_eq!(parse_notion_id(case).unwrap(), id, "{case}");
+        }
+    }
+
+    #[test]
+    fn invalid_notion_ids() {
+        assert!(parse_notion_id("https://www.notion.so/workspace/Books").is_err());
+        assert!(parse_notion_id("0123456789abcdef").is_err());
+        assert!(parse_notion_id(
+            "https://www.notion.so/workspace/Books?v=fedcba9876543210fedcba9876543210"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn search_fetches_descriptions_in_order() {
+        let notion = Notion::mock(|method, endpoint, _body| {
+            let page = |id: &str| {
+                json!({
+                    "id": id,
+                    "properties": {
+                        "Name": { "title": [{ "plain_text": "Dune" }] },
+                        "Authors": { "multi_select": [] },
+                        "Publish Date": { "rich_text": [] },
+                        "ISBN": { "rich_text": [] },
+                    }
+                })
+            };
+            let response = match (method.as_str(), endpoint) {
+                ("POST", "/databases/db/query") => json!({
+                    "results": [page("p0"), page("p1"), page("p2")],
+                    "has_more": false,
+                    "next_cursor": null,
+                }),
+                ("GET", "/blocks/p1/children") => json!({ "results": [{ "id": "block" }] }),
+                ("GET", _) => json!({ "results": [] }),
+                _ => json!({}),
+            };
+            (StatusCode::OK, response)
+        });
+        let database = notion
+            .database(
+                "db".to_string(),
+                DatabaseOptions {
+                    description_concurrency: Some(2),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let results = database.search("Dune").await.unwrap();
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|entry| (entry.id.as_deref(), entry.had_original_description))
+                .collect::<Vec<_>>(),
+            [(Some("p0"), false), (Some("p1"), true), (Some("p2"), false)]
+        );
+    }
+
+    #[test]
+    fn title_with_multiple_segments() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [
+                    { "plain_text": "The " },
+                    { "plain_text": "Left Hand", "annotations": { "italic": true } },
+                    { "plain_text": " of Darkness " },
+                ] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        assert_eq!(entry.title, "The Left Hand of Darkness");
+    }
+
+    #[test]
+    fn isbn_and_date_with_multiple_segments() {
+        let page = json!({
+            "id": "page-id",
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [
+                    { "plain_text": "1965-" },
+                    { "plain_text": "08-01" },
+                ] },
+                "ISBN": { "rich_text": [
+                    { "plain_text": " 978" },
+                    { "plain_text": "0441013593 " },
+                ] },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        assert_eq!(entry.published_date.as_deref(), Some("1965-08-01"));
+        assert_eq!(entry.isbn.as_deref(), Some("9780441013593"));
+    }
+
+    fn entry(id: &str, isbn: Option<&str>) -> NotionBookEntry {
+        NotionBookEntry {
+            id: Some(id.to_string()),
+            isbn: isbn.map(str::to_string),
+            ..NotionBookEntry::new("Dune")
+        }
+    }
+
+    #[test]
+    fn duplicates_are_grouped_by_isbn() {
+        let groups = group_by_isbn(vec![
+            entry("a", Some("978-0441013593")),
+            entry("b", Some("9780441172719")),
+            entry("c", None),
+            entry("d", Some("9780441013593")),
+        ]);
+        let ids = groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|entry| entry.id.as_deref().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [["a", "d"]]);
+    }
+
+    #[test]
+    fn merge_keeps_most_complete_entry() {
+        let mut sparse = entry("sparse", Some("9780441013593"));
+        sparse.owned = true;
+        sparse.edition = Some("2nd Edition".to_string());
+        sparse.notes = Some("Signed".to_string());
+
+        let mut complete = entry("complete", Some("9780441013593"));
+        complete.authors = vec!["Frank Herbert".to_string()];
+        complete.author_ids = vec![Some("herbert".to_string())];
+        complete.publisher = Some("Ace".to_string());
+        complete.notes = Some("Reread".to_string());
+
+        let merged = NotionBookEntry::merge(vec![sparse, complete]);
+        assert_eq!(merged.id.as_deref(), Some("complete"));
+        assert!(merged.owned);
+        assert_eq!(merged.authors, ["Frank Herbert"]);
+        assert_eq!(merged.publisher.as_deref(), Some("Ace"));
+        assert_eq!(merged.edition.as_deref(), Some("2nd Edition"));
+        assert_eq!(merged.notes.as_deref(), Some("Reread\nSigned"));
+    }
+
+    #[test]
+    fn merge_prefers_first_of_equally_complete_entries() {
+        let merged = NotionBookEntry::merge(vec![
+            entry("first", Some("9780441013593")),
+            entry("second", Some("9780441013593")),
+        ]);
+        assert_eq!(merged.id.as_deref(), Some("first"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter_spaces_out_requests() {
+        let rate_limiter = RateLimiter::new(20);
+        let start = Instant::now();
+
+        // The first request can start right away, the others have to wait 50ms each. The clock is
+        // paused, so it only advances as far as the waiting requires.
+        future::join_all((0..4).map(|_| rate_limiter.wait())).await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(150));
+    }
+
+    fn mock_notion_with_databases(titles: &'static [&'static str]) -> Notion {
+        Notion::mock(move |_method, _endpoint, _body| {
+            let results = titles
+                .iter()
+                .enumerate()
+                .map(|(i, title)| {
+                    json!({
+                        "object": "database",
+                        "id": format!("db-{i}"),
+                        "title": [{ "plain_text": title }],
+                    })
+                })
+                .collect::<Vec<_>>();
+            (
+                StatusCode::OK,
+                json!({ "results": results, "has_more": false }),
+            )
+        })
+    }
+
+    #[tokio::test]
+    async fn database_is_found_by_name() {
+        let notion = mock_notion_with_databases(&["Books to read", "books"]);
+        let id = notion.find_database_by_name("Books").await.unwrap();
+        assert_eq!(id, "db-1");
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(mock.calls(), ["POST /search"]);
+        assert_eq!(
+            mock.calls.lock().unwrap()[0].2,
+            Some(json!({
+                "query": "Books",
+                "filter": { "property": "object", "value": "database" },
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn database_name_must_be_unique() {
+        let notion = mock_notion_with_databases(&["Books", "Books"]);
+        let err = notion.find_database_by_name("Books").await.unwrap_err();
+        assert!(err.to_string().contains("several databases"));
+
+        let notion = mock_notion_with_databases(&["Books to read"]);
+        let err = notion.find_database_by_name("Books").await.unwrap_err();
+        assert!(err.to_string().contains("No database named"));
+    }
+
+    #[test]
+    fn page_authorship_is_read() {
+        let page = json!({
+            "id": "page-id",
+            "created_by": { "object": "user", "id": "user-a" },
+            "last_edited_by": { "object": "user", "id": "user-b" },
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        assert_eq!(entry.created_by.as_deref(), Some("user-a"));
+        assert_eq!(entry.last_edited_by.as_deref(), Some("user-b"));
+    }
+
+    #[tokio::test]
+    async fn user_name_is_looked_up() {
+        let notion = Notion::mock(|_method, _endpoint, _body| {
+            (
+                StatusCode::OK,
+                json!({ "object": "user", "id": "user-a", "name": "Ada" }),
+            )
+        });
+        assert_eq!(notion.user_name("user-a").await.unwrap(), "Ada");
+        assert_eq!(notion.mock.as_ref().unwrap().calls(), ["GET /users/user-a"]);
+    }
+
+    #[tokio::test]
+    async fn archive_entry_patches_page() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database.archive_entry("page-id").await.unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(mock.calls(), ["GET /databases/db", "PATCH /pages/page-id"]);
+        assert_eq!(
+            mock.calls.lock().unwrap()[1].2,
+            Some(json!({ "archived": true }))
+        );
+    }
+
+    #[tokio::test]
+    async fn update_cover_patches_only_cover() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database
+            .update_cover("page-id", "https://example.com/cover.jpg")
+            .await
+            .unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(mock.calls(), ["GET /databases/db", "PATCH /pages/page-id"]);
+        assert_eq!(
+            mock.calls.lock().unwrap()[1].2,
+            Some(json!({ "cover": { "external": { "url": "https://example.com/cover.jpg" } } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn update_isbn_patches_only_isbn() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database
+            .update_isbn("page-id", "9780441013593")
+            .await
+            .unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(mock.calls(), ["GET /databases/db", "PATCH /pages/page-id"]);
+        assert_eq!(
+            mock.calls.lock().unwrap()[1].2,
+            Some(json!({ "properties": {
+                "ISBN": { "rich_text": [{ "text": { "content": "9780441013593" } }] }
+            } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn set_ownership_patches_only_ownership() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let database = notion
+            .database("db".to_string(), custom_ownership_options())
+            .await
+            .unwrap();
+
+        database.set_ownership("page-id", None).await.unwrap();
+        database
+            .set_ownership("page-id", Some("Lent out"))
+            .await
+            .unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls(),
+            [
+                "GET /databases/db",
+                "PATCH /pages/page-id",
+                "PATCH /pages/page-id"
+            ]
+        );
+        let calls = mock.calls.lock().unwrap();
+        assert_eq!(
+            calls[1].2,
+            Some(json!({ "properties": { "Have": { "select": { "name": "Yes" } } } }))
+        );
+        assert_eq!(
+            calls[2].2,
+            Some(json!({ "properties": { "Have": { "select": { "name": "Lent out" } } } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn checkbox_ownership_can_only_be_checked() {
+        let notion = Notion::mock(|_method, _endpoint, _body| (StatusCode::OK, json!({})));
+        let database = notion
+            .database("db".to_string(), checkbox_ownership_options())
+            .await
+            .unwrap();
+
+        assert!(database.check_ownership_value(None).is_ok());
+        assert!(database.check_ownership_value(Some("Lent out")).is_err());
+        database.set_ownership("page-id", None).await.unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls.lock().unwrap()[1].2,
+            Some(json!({ "properties": { "Owned": { "checkbox": true } } }))
+        );
+    }
+
+    #[test]
+    fn uploaded_cover_is_recognized() {
+        let mut page = json!({
+            "id": "page-id",
+            "cover": {
+                "type": "file",
+                "file": { "url": "https://s3.example.com/cover.jpg", "expiry_time": "2022-01-01" }
+            },
+            "properties": {
+                "Name": { "title": [{ "plain_text": "Dune" }] },
+                "Authors": { "multi_select": [] },
+                "Publish Date": { "rich_text": [] },
+                "ISBN": { "rich_text": [] },
+            }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        assert!(entry.cover_uploaded);
+        assert_eq!(entry.cover_url, None);
+
+        page["cover"] = json!({
+            "type": "external",
+            "external": { "url": "https://example.com/cover.jpg" }
+        });
+        let entry = NotionBookEntry::from_page(&page, &DatabaseOptions::default()).unwrap();
+        assert!(!entry.cover_uploaded);
+        assert_eq!(
+            entry.cover_url.as_deref(),
+            Some("https://example.com/cover.jpg")
+        );
+    }
+
+    fn page_to_update() -> NotionBookEntry {
+        NotionBookEntry {
+            id: Some("page-id".to_string()),
+            had_original_description: true,
+            description: Some(vec![Block::Paragraph(RichText {
+                fragments: vec![TextFragment::new("New description", TextStyle::unstyled())],
+            })]),
+            ..NotionBookEntry::new("Dune")
+        }
+    }
+
+    fn mock_notion_with_page_body() -> Notion {
+        Notion::mock(|method, endpoint, _body| {
+            let response = match (method.as_str(), endpoint) {
+                ("GET", "/blocks/page-id/children") => json!({
+                    "results": [{ "id": "old-1" }, { "id": "old-2" }],
+                    "has_more": false,
+                    "next_cursor": null,
+                }),
+                _ => json!({}),
+            };
+            (StatusCode::OK, response)
+        })
+    }
+
+    #[tokio::test]
+    async fn replace_description_deletes_old_body_first() {
+        let notion = mock_notion_with_page_body();
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database.update_entry(page_to_update(), true).await.unwrap();
+
+        assert_eq!(
+            notion.mock.as_ref().unwrap().calls(),
+            [
+                "GET /databases/db",
+                "PATCH /pages/page-id",
+                "GET /blocks/page-id/children",
+                "DELETE /blocks/old-1",
+                "DELETE /blocks/old-2",
+                "PATCH /blocks/page-id/children",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_description_is_kept_by_default() {
+        let notion = mock_notion_with_page_body();
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database
+            .update_entry(page_to_update(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            notion.mock.as_ref().unwrap().calls(),
+            ["GET /databases/db", "PATCH /pages/page-id"]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_description_leaves_properties_alone() {
+        let notion = mock_notion_with_page_body();
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        let mut entry = page_to_update();
+        entry.had_original_description = false;
+        assert!(!database
+            .update_description(entry.clone(), false)
+            .await
+            .unwrap());
+        assert!(database.update_description(entry, true).await.unwrap());
+
+        assert_eq!(
+            notion.mock.as_ref().unwrap().calls(),
+            [
+                "GET /databases/db",
+                "GET /blocks/page-id/children",
+                "GET /blocks/page-id/children",
+                "GET /blocks/page-id/children",
+                "DELETE /blocks/old-1",
+                "DELETE /blocks/old-2",
+                "PATCH /blocks/page-id/children",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn description_is_written_to_child_page() {
+        let notion = Notion::mock(|method, endpoint, _body| {
+            let response = match (method.as_str(), endpoint) {
+                ("POST", "/pages/") => json!({ "id": "child-id" }),
+                ("GET", "/blocks/page-id/children") => json!({
+                    "results": [{ "id": "old-1" }],
+                    "has_more": false,
+                    "next_cursor": null,
+                }),
+                _ => json!({}),
+            };
+            (StatusCode::OK, response)
+        });
+        let options = DatabaseOptions {
+            description_child_page: true,
+            ..Default::default()
+        };
+        let database = notion.database("db".to_string(), options).await.unwrap();
+
+        database.update_entry(page_to_update(), true).await.unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls(),
+            [
+                "GET /databases/db",
+                "PATCH /pages/page-id",
+                "GET /blocks/page-id/children",
+                "DELETE /blocks/old-1",
+                "POST /pages/",
+                "PATCH /blocks/child-id/children",
+            ]
+        );
+        let calls = mock.calls.lock().unwrap();
+        assert_eq!(
+            calls[4].2,
+            Some(json!({
+                "parent": { "page_id": "page-id" },
+                "properties": {
+                    "title": { "title": [{ "text": { "content": "Description" } }] }
+                }
+            }))
+        );
+        assert_eq!(
+            calls[5].2.as_ref().unwrap()["children"][0]["paragraph"]["rich_text"][0]["text"]
+                ["content"],
+            "New description"
+        );
+    }
+
+    #[tokio::test]
+    async fn long_descriptions_are_appended_in_batches() {
+        let notion = mock_notion_with_page_body();
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+        let sentence = Block::Paragraph(RichText {
+            fragments: vec![TextFragment::new("A sentence.", TextStyle::unstyled())],
+        });
+        let entry = NotionBookEntry {
+            description: Some(vec![sentence; 250]),
+            ..page_to_update()
+        };
+
+        database.update_entry(entry, true).await.unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls(),
+            [
+                "GET /databases/db",
+                "PATCH /pages/page-id",
+                "GET /blocks/page-id/children",
+                "DELETE /blocks/old-1",
+                "DELETE /blocks/old-2",
+                "PATCH /blocks/page-id/children",
+                "PATCH /blocks/page-id/children",
+                "PATCH /blocks/page-id/children",
+            ]
+        );
+        let calls = mock.calls.lock().unwrap();
+        let appended = calls[5..]
+            .iter()
+            .map(|(_, _, body)| body.as_ref().unwrap()["children"].as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(appended, [100, 100, 50]);
+    }
+
+    #[tokio::test]
+    async fn missing_database_access_is_explained() {
+        let notion = Notion::mock(|_method, _endpoint, _body| {
+            (
+                StatusCode::NOT_FOUND,
+                json!({
+                    "object": "error",
+                    "status": 404,
+                    "code": "object_not_found",
+                    "message": "Could not find database with ID: db."
+                }),
+            )
+        });
+        let error = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap_err();
+
+        let message = format!("{error:?}");
+        assert!(
+            message.contains("Share the database with your integration"),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn other_api_errors_are_kept() {
+        let notion = Notion::mock(|_method, _endpoint, _body| {
+            (
+                StatusCode::BAD_REQUEST,
+                json!({ "code": "validation_error" }),
+            )
+        });
+        let error = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap_err();
+
+        let message = format!("{error:?}");
+        assert!(message.contains("validation_error"), "{message}");
+    }
+
+    fn mock_notion_with_schema(properties: Value) -> Notion {
+        Notion::mock(move |method, endpoint, _body| {
+            let response = match (method.as_str(), endpoint) {
+                ("GET", "/databases/db") => json!({ "properties": properties.clone() }),
+                _ => json!({}),
+            };
+            (StatusCode::OK, response)
+        })
+    }
+
+    #[tokio::test]
+    async fn missing_property_gives_helpful_error() {
+        let notion = mock_notion_with_schema(json!({
+            "Name": { "type": "title" },
+            "Type": { "type": "select" },
+            "Authors": { "type": "multi_select" },
+            "Publish Date": { "type": "date" },
+        }));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        let mut entry = page_to_update();
+        entry.published_date = Some("1965".to_string());
+        entry.isbn = Some("9780441013593".to_string());
+        let error = database.update_entry(entry, false).await.unwrap_err();
+
+        let message = format!("{error:?}");
+        assert!(
+            message.contains("property 'ISBN' (rich_text) is missing; create it in your database")
+        );
+        assert!(
+            message.contains("property 'Publish Date' has type date, but needs to be rich_text")
+        );
+        // Nothing was written.
+        assert_eq!(notion.mock.as_ref().unwrap().calls(), ["GET /databases/db"]);
+    }
+
+    #[tokio::test]
+    async fn since_queries_entries_not_synced_since() {
+        let notion = Notion::mock(|_method, endpoint, _body| match endpoint {
+            "/databases/db/query" => (StatusCode::OK, json!({ "results": [] })),
+            _ => (StatusCode::OK, json!({})),
+        });
+        let options = DatabaseOptions {
+            properties: PropertyNames {
+                last_synced: Some("Last Synced".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let database = notion.database("db".to_string(), options).await.unwrap();
+        let filter = database.not_synced_since("2024-01-31").unwrap();
+        database.query(&filter).await.unwrap();
+
+        let mock = notion.mock.as_ref().unwrap();
+        assert_eq!(
+            mock.calls(),
+            ["GET /databases/db", "POST /databases/db/query"]
+        );
+        assert_eq!(
+            mock.calls.lock().unwrap()[1].2,
+            Some(json!({ "filter": {
+                "and": [
+                    { "property": "ISBN", "rich_text": { "is_not_empty": true } },
+                    {
+                        "or": [
+                            { "property": "Last Synced", "date": { "is_empty": true } },
+                            { "property": "Last Synced", "date": { "before": "2024-01-31" } },
+                        ]
+                    },
+                ]
+            } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn since_needs_last_synced_property() {
+        let notion = mock_notion_with_schema(json!({}));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+        let error = database.not_synced_since("2024-01-31").unwrap_err();
+        assert!(format!("{error:?}").contains("last-synced"));
+    }
+
+    #[tokio::test]
+    async fn book_type_is_picked_from_select_options() {
+        let notion = mock_notion_with_schema(json!({
+            "Type": { "type": "select", "select": { "options": [
+                { "id": "1", "name": "Comic", "color": "red" },
+                { "id": "2", "name": "Novel", "color": "blue" },
+            ] } },
+        }));
+        let options = DatabaseOptions {
+            book_type: Some("novel".to_string()),
+            ..Default::default()
+        };
+        let database = notion.database("db".to_string(), options).await.unwrap();
+        assert_eq!(database.options.book_type(), "Novel");
+        assert_eq!(
+            properties_from_entry(page_to_update(), &database.options)["Type"],
+            json!({ "select": { "name": "Novel" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_book_type_option_is_an_error() {
+        let notion = mock_notion_with_schema(json!({
+            "Type": { "type": "select", "select": { "options": [
+                { "id": "1", "name": "Comic", "color": "red" },
+            ] } },
+        }));
+        let error = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap_err();
+
+        let message = format!("{error:?}");
+        assert!(
+            message.contains("has no option \"Book\" (it has: Comic)"),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn matching_schema_is_accepted() {
+        let notion = mock_notion_with_schema(json!({
+            "Name": { "type": "title" },
+            "Type": { "type": "select" },
+            "Authors": { "type": "multi_select" },
+            "Ownership": { "type": "select" },
+        }));
+        let database = notion
+            .database("db".to_string(), DatabaseOptions::default())
+            .await
+            .unwrap();
+
+        database
+            .update_entry(page_to_update(), false)
+            .await
+            .unwrap();
+    }
+}
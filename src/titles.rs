@@ -0,0 +1,142 @@
+// Formatting the Name of new entries from a template like "{first_author} - {title}", for those who
+// want more than the plain title in it.
+//
+// Templates are parsed when the configuration is read, so that a misspelled placeholder is reported
+// right away instead of ending up in every title.
+
+use miette::{miette, Result};
+
+use crate::{dates::PublishedDate, gbooks::GBook};
+
+const PLACEHOLDERS: [&str; 4] = ["title", "subtitle", "year", "first_author"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleTemplate {
+    parts: Vec<Part>,
+}
+
+impl TitleTemplate {
+    /// Parse a template with placeholders in braces: `{title}`, `{subtitle}`, `{year}` (of
+    /// publication) and `{first_author}`.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(miette!("Unmatched '}}' in title template \"{}\"", template));
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| miette!("Unclosed '{{' in title template \"{}\"", template))?;
+            let name = rest[start + 1..start + end].trim();
+            let placeholder = PLACEHOLDERS
+                .into_iter()
+                .find(|placeholder| *placeholder == name)
+                .ok_or_else(|| {
+                    miette!(
+                        "Unknown placeholder {{{name}}} in title template \"{template}\"; known are {}",
+                        PLACEHOLDERS.map(|placeholder| format!("{{{placeholder}}}")).join(", ")
+                    )
+                })?;
+            parts.push(Part::Placeholder(placeholder));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// The title for `gbook`. Placeholders without a value are left empty, and so are brackets
+    /// around them, so that "{title} ({year})" is just the title for a book without a date.
+    pub fn render(&self, gbook: &GBook) -> String {
+        let mut title = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => title.push_str(text),
+                Part::Placeholder(name) => title.push_str(&value(gbook, name).unwrap_or_default()),
+            }
+        }
+        let title = title.replace("()", "").replace("[]", "");
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        title
+            .trim_matches(|c: char| c == '-' || c == ':' || c == ',' || c.is_whitespace())
+            .to_string()
+    }
+}
+
+fn value(gbook: &GBook, placeholder: &str) -> Option<String> {
+    match placeholder {
+        "title" => Some(gbook.title.clone()),
+        "subtitle" => gbook.subtitle.clone(),
+        "year" => gbook
+            .published_date
+            .as_deref()
+            .and_then(PublishedDate::parse)
+            .map(|date| date.year.to_string()),
+        "first_author" => gbook.authors.first().cloned(),
+        _ => unreachable!("unknown placeholder {placeholder}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(subtitle: Option<&str>, authors: &[&str], published_date: Option<&str>) -> GBook {
+        GBook {
+            subtitle: subtitle.map(str::to_string),
+            authors: authors.iter().map(|a| a.to_string()).collect(),
+            published_date: published_date.map(str::to_string),
+            ..GBook::new("Dune")
+        }
+    }
+
+    fn render(template: &str, gbook: &GBook) -> String {
+        TitleTemplate::parse(template).unwrap().render(gbook)
+    }
+
+    #[test]
+    fn templates_are_rendered() {
+        let book = book(
+            Some("Deluxe Edition"),
+            &["Frank Herbert", "Brian Herbert"],
+            Some("2019-10-01"),
+        );
+        assert_eq!(render("{title}", &book), "Dune");
+        assert_eq!(render("{title}: {subtitle}", &book), "Dune: Deluxe Edition");
+        assert_eq!(render("{title} ({year})", &book), "Dune (2019)");
+        assert_eq!(
+            render("{first_author} - {title}", &book),
+            "Frank Herbert - Dune"
+        );
+        assert_eq!(render("{ title }", &book), "Dune");
+    }
+
+    #[test]
+    fn missing_values_are_left_out() {
+        let book = book(None, &[], None);
+        assert_eq!(render("{title}: {subtitle}", &book), "Dune");
+        assert_eq!(render("{title} ({year})", &book), "Dune");
+        assert_eq!(render("{first_author} - {title}", &book), "Dune");
+        assert_eq!(render("{title} [{year}]", &book), "Dune");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(TitleTemplate::parse("{title} ({author})").is_err());
+        assert!(TitleTemplate::parse("{title").is_err());
+        assert!(TitleTemplate::parse("title}").is_err());
+        assert!(TitleTemplate::parse("{}").is_err());
+        assert!(TitleTemplate::parse("Dune").is_ok());
+    }
+}
@@ -0,0 +1,343 @@
+// All interaction with the user goes through the `Ui` trait, so that the decision logic in `main`
+// can be driven by something other than a terminal (most importantly, by tests).
+//
+// Implementations only need to provide the two primitive operations, `report` and `prompt`; the
+// higher-level `select` and `confirm` are built on top of those so that their input handling is
+// shared between all implementations.
+
+use miette::{miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use std::{
+    fmt::Display,
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use crate::locale::Locale;
+
+pub trait Ui {
+    /// Show a line of output to the user.
+    fn report(&mut self, message: &str);
+
+    /// Show `message` (without a trailing newline) and read a line of input. Fails with
+    /// `EndOfInput` if there is no more input.
+    fn prompt(&mut self, message: &str) -> Result<String>;
+
+    /// How to write dates and numbers in output.
+    fn locale(&self) -> Locale {
+        Locale::default()
+    }
+
+    /// Tell the user that something they asked for was done, like adding a book.
+    fn success(&mut self, message: &str) {
+        self.report(message);
+    }
+
+    /// Show progress retrieving search results, `done` out of `total`.
+    fn progress(&mut self, _done: usize, _total: usize) {}
+
+    /// Show progress of a long-running operation over many books, `done` out of `total`, with
+    /// `current` being worked on now.
+    fn item_progress(&mut self, _done: usize, _total: usize, _current: &str) {}
+
+    /// Let the user choose one of `options`, returning its index.
+    fn select(&mut self, message: &str, options: &[String]) -> Result<usize> {
+        self.select_with_default(message, options, None)
+    }
+
+    /// Like `select`, but `default` (if any) is marked as suggested and chosen by entering
+    /// nothing.
+    fn select_with_default(
+        &mut self,
+        message: &str,
+        options: &[String],
+        default: Option<usize>,
+    ) -> Result<usize> {
+        self.report(message);
+        for (i, option) in options.iter().enumerate() {
+            if Some(i) == default {
+                self.report(&format!("{i}: {option} (suggested)"));
+            } else {
+                self.report(&format!("{i}: {option}"));
+            }
+        }
+
+        let input = self.prompt("> ")?;
+        if let (Some(default), "") = (default, input.trim()) {
+            return Ok(default);
+        }
+        let choice = input
+            .parse::<usize>()
+            .into_diagnostic()
+            .wrap_err("Invalid choice")?;
+        if choice >= options.len() {
+            return Err(miette!("Invalid choice {}", choice));
+        }
+
+        Ok(choice)
+    }
+
+    /// Ask the user a yes/no question.
+    fn confirm(&mut self, message: &str) -> Result<bool> {
+        self.report(&format!("{message} (Y/N)"));
+        let choice = self.prompt("> ")?;
+        Ok(matches!(choice.as_str(), "Y" | "y" | "Yes" | "yes"))
+    }
+}
+
+/// The error returned by `Ui::prompt` when the input has ended, e.g. because stdin was closed.
+#[derive(Debug)]
+pub struct EndOfInput;
+
+impl Display for EndOfInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Reached the end of the input")
+    }
+}
+
+impl std::error::Error for EndOfInput {}
+impl Diagnostic for EndOfInput {}
+
+pub struct TerminalUi {
+    /// Whether to show progress at all. Progress output only makes sense on an actual terminal.
+    show_progress: bool,
+    /// Whether the last line of output is a progress bar, which is overwritten by further output.
+    bar_visible: bool,
+    /// Input entered quicker than this after a prompt is ignored, see `read_debounced`.
+    debounce: Duration,
+    /// Whether to ring the terminal bell on success.
+    beep: bool,
+    locale: Locale,
+}
+
+impl TerminalUi {
+    pub fn new(quiet: bool) -> Self {
+        Self {
+            show_progress: !quiet && std::io::stdout().is_terminal(),
+            bar_visible: false,
+            debounce: Duration::ZERO,
+            beep: false,
+            locale: Locale::default(),
+        }
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn with_beep(mut self, beep: bool) -> Self {
+        self.beep = beep;
+        self
+    }
+
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    fn clear_bar(&mut self) {
+        if self.bar_visible {
+            print!("\r\x1b[K");
+            self.bar_visible = false;
+        }
+    }
+}
+
+impl Ui for TerminalUi {
+    fn report(&mut self, message: &str) {
+        self.clear_bar();
+        println!("{message}");
+    }
+
+    fn prompt(&mut self, message: &str) -> Result<String> {
+        self.clear_bar();
+        print!("{message}");
+        let line = read_debounced(self.debounce, read_stdin_line, |line| {
+            println!("(Ignoring input entered too quickly: \"{line}\")");
+            print!("{message}");
+        })?;
+        match line {
+            Some(line) => Ok(line),
+            None => {
+                // Move past the prompt, since no newline was entered.
+                println!();
+                Err(EndOfInput.into())
+            }
+        }
+    }
+
+    fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    fn success(&mut self, message: &str) {
+        self.report(message);
+        if self.beep {
+            print!("\x07");
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    fn progress(&mut self, done: usize, total: usize) {
+        if !self.show_progress {
+            return;
+        }
+        // Overwrite the same line until we're done.
+        print!("\rFetched {done}/{total} results");
+        if done == total {
+            println!();
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    fn item_progress(&mut self, done: usize, total: usize, current: &str) {
+        if !self.show_progress {
+            return;
+        }
+        print!("\r\x1b[K{}", progress_bar(done, total, current));
+        self.bar_visible = done < total;
+        if done == total {
+            println!();
+        }
+        let _ = std::io::stdout().flush();
+    }
+}
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+const PROGRESS_TITLE_CHARS: usize = 40;
+
+/// Render a line like `[#####-----] 5/10 Current title`.
+fn progress_bar(done: usize, total: usize, current: &str) -> String {
+    let filled = (done * PROGRESS_BAR_WIDTH).checked_div(total).unwrap_or(0);
+    let filled = filled.min(PROGRESS_BAR_WIDTH);
+    let mut line = format!(
+        "[{}{}] {done}/{total}",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled)
+    );
+    if !current.is_empty() {
+        line.push(' ');
+        match current.char_indices().nth(PROGRESS_TITLE_CHARS) {
+            Some((end, _)) => {
+                line.push_str(&current[..end]);
+                line.push('…');
+            }
+            None => line.push_str(current),
+        }
+    }
+    line
+}
+
+/// Read a line with `read`, ignoring lines that arrive less than `debounce` after starting to wait
+/// for them. Nobody answers that quickly, so those were entered before the prompt was even shown,
+/// like a barcode scanned while the previous book was still being added. `ignored` is called with
+/// each ignored line.
+fn read_debounced(
+    debounce: Duration,
+    mut read: impl FnMut() -> Result<Option<String>>,
+    mut ignored: impl FnMut(&str),
+) -> Result<Option<String>> {
+    loop {
+        let waiting_since = Instant::now();
+        match read()? {
+            Some(line) if waiting_since.elapsed() < debounce => ignored(&line),
+            line => return Ok(line),
+        }
+    }
+}
+
+/// Read a line from stdin, without the trailing newline. Returns `None` at the end of the input.
+fn read_stdin_line() -> Result<Option<String>> {
+    std::io::stdout().flush().into_diagnostic()?;
+    let mut buf = String::new();
+    let read = std::io::stdin().read_line(&mut buf).into_diagnostic()?;
+    if read == 0 {
+        return Ok(None);
+    }
+    buf.truncate(buf.trim_end().len());
+    Ok(Some(buf))
+}
+
+/// A `Ui` that answers prompts from a fixed list of inputs and records all output.
+#[cfg(test)]
+pub struct ScriptedUi {
+    inputs: std::collections::VecDeque<String>,
+    pub output: Vec<String>,
+}
+
+#[cfg(test)]
+impl ScriptedUi {
+    pub fn new(inputs: &[&str]) -> Self {
+        Self {
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Ui for ScriptedUi {
+    fn report(&mut self, message: &str) {
+        self.output.push(message.to_string());
+    }
+
+    fn prompt(&mut self, message: &str) -> Result<String> {
+        self.output.push(message.to_string());
+        self.inputs.pop_front().ok_or_else(|| EndOfInput.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quick_input_is_ignored() {
+        let mut inputs = vec![
+            (Duration::ZERO, "9780441013593"),
+            (Duration::from_millis(50), "9780593098233"),
+        ]
+        .into_iter();
+        let read = || {
+            Ok(inputs.next().map(|(delay, line)| {
+                std::thread::sleep(delay);
+                line.to_string()
+            }))
+        };
+        let mut ignored = Vec::new();
+
+        let line = read_debounced(Duration::from_millis(20), read, |line| {
+            ignored.push(line.to_string())
+        });
+        assert_eq!(line.unwrap().as_deref(), Some("9780593098233"));
+        assert_eq!(ignored, ["9780441013593"]);
+    }
+
+    #[test]
+    fn without_debounce_all_input_is_taken() {
+        let mut inputs = vec!["9780441013593"].into_iter();
+        let read = || Ok(inputs.next().map(str::to_string));
+        let line = read_debounced(Duration::ZERO, read, |_| panic!("Nothing is ignored"));
+        assert_eq!(line.unwrap().as_deref(), Some("9780441013593"));
+    }
+
+    #[test]
+    fn progress_bar_rendering() {
+        assert_eq!(
+            progress_bar(0, 3, "Dune"),
+            "[------------------------------] 0/3 Dune"
+        );
+        assert_eq!(
+            progress_bar(1, 3, ""),
+            "[##########--------------------] 1/3"
+        );
+        assert_eq!(
+            progress_bar(0, 0, ""),
+            "[------------------------------] 0/0"
+        );
+        assert_eq!(
+            progress_bar(3, 3, &"x".repeat(50)),
+            format!("[{}] 3/3 {}…", "#".repeat(30), "x".repeat(40))
+        );
+    }
+}